@@ -0,0 +1,17 @@
+-- -----------------------------------------------------------------------------
+-- Allow multiple answers per player per round
+-- -----------------------------------------------------------------------------
+
+alter table games
+    add max_answers_per_round integer default 1 not null
+        constraint games_max_answers_per_round_check
+            check (max_answers_per_round > 0);
+
+alter table answers
+    drop constraint answers_round_id_player_id_key;
+
+alter table rounds
+    add winning_answer uuid
+        constraint rounds_answers_id_fk
+            references answers
+            on delete set null;
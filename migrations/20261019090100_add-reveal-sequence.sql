@@ -0,0 +1,17 @@
+-- -----------------------------------------------------------------------------
+-- Add shuffled reveal order and automatic reveal settings
+-- -----------------------------------------------------------------------------
+
+alter table games
+    add auto_reveal             boolean default false not null,
+    add reveal_interval_seconds integer default 3     not null
+        constraint games_reveal_interval_seconds_check
+            check (reveal_interval_seconds >= 0);
+
+comment on column games.auto_reveal is 'Whether answers are revealed automatically once they are closed';
+
+alter table rounds
+    add reveal_order uuid[]  default '{}'::uuid[] not null,
+    add revealing    boolean default false        not null;
+
+comment on column rounds.reveal_order is 'Shuffled answer ids, in the order they are revealed';
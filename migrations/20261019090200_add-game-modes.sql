@@ -0,0 +1,15 @@
+-- -----------------------------------------------------------------------------
+-- Add game modes and round prompts
+-- -----------------------------------------------------------------------------
+
+create type game_mode as enum ('caption', 'guess_title', 'fill_in_the_blank');
+
+alter table games
+    add mode    game_mode default 'caption'::game_mode not null,
+    add prompts varchar[] default '{}'::varchar[]      not null;
+
+comment on column games.prompts is 'Array of prompts matching image_urls, empty for no prompt';
+
+alter table rounds
+    add mode   game_mode default 'caption'::game_mode not null,
+    add prompt varchar;
@@ -0,0 +1,48 @@
+-- -----------------------------------------------------------------------------
+-- Add guess the answer mode with automatic scoring
+-- -----------------------------------------------------------------------------
+
+alter type game_mode add value 'guess_the_answer';
+
+alter table games
+    add answer_tolerance integer default 1 not null
+        constraint games_answer_tolerance_check
+            check (answer_tolerance >= 0);
+
+comment on column games.answer_tolerance is 'Number of typos allowed when matching answers against answer keys';
+
+create table answer_keys
+(
+    id              uuid      default gen_random_uuid() not null
+        constraint answer_keys_pk
+            primary key,
+    game_id         uuid                                not null
+        constraint answer_keys_games_id_fk
+            references games
+            on delete cascade,
+    image_index     integer                             not null,
+    expected_answer varchar                             not null,
+    aliases         varchar[] default '{}'::varchar[]   not null,
+    created         timestamp default now()             not null,
+    updated         timestamp default now()             not null,
+    constraint answer_keys_game_id_image_index_key
+        unique (game_id, image_index)
+);
+
+create trigger update_answer_keys_updated
+    before update
+    on answer_keys
+    for each row
+execute procedure mod_datetime();
+
+alter table answers
+    add correct boolean;
+
+comment on column answers.correct is 'Whether the answer matched the answer key, null when not scored automatically';
+
+alter table rounds
+    add ended boolean default false not null;
+
+update rounds
+set ended = true
+where round_winner is not null;
@@ -0,0 +1,16 @@
+-- -----------------------------------------------------------------------------
+-- Allow late joiners and observers to become players
+-- -----------------------------------------------------------------------------
+
+create type late_join as enum ('observer', 'next_round', 'catch_up');
+
+alter table games
+    add late_join late_join default 'observer'::late_join not null;
+
+comment on column games.late_join is 'How players joining after the game has started take part';
+
+alter table players
+    add first_round         integer,
+    add promotion_requested boolean default false not null;
+
+comment on column players.first_round is 'First round the player may answer in, null for every round';
@@ -0,0 +1,8 @@
+-- -----------------------------------------------------------------------------
+-- Track host presence
+-- -----------------------------------------------------------------------------
+
+alter table games
+    add host_active boolean default false not null;
+
+comment on column games.host_active is 'Whether the owner or co-host is connected to the game';
@@ -0,0 +1,30 @@
+-- -----------------------------------------------------------------------------
+-- Create game members table
+-- -----------------------------------------------------------------------------
+
+create table game_members
+(
+    id      uuid      default gen_random_uuid() not null
+        constraint game_members_pk
+            primary key,
+    game_id uuid                                not null
+        constraint game_members_games_id_fk
+            references games
+            on delete cascade,
+    user_id uuid                                not null
+        constraint game_members_users_id_fk
+            references users
+            on delete cascade,
+    created timestamp default now()             not null,
+    updated timestamp default now()             not null,
+    constraint game_members_game_id_user_id_key
+        unique (game_id, user_id)
+);
+
+comment on table game_members is 'Registered users the owner has allowed to run the game as a co-host';
+
+create trigger update_game_members_updated
+    before update
+    on game_members
+    for each row
+execute procedure mod_datetime();
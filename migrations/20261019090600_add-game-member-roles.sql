@@ -0,0 +1,16 @@
+-- -----------------------------------------------------------------------------
+-- Add roles for the registered users helping to run a game
+-- -----------------------------------------------------------------------------
+
+create type game_role as enum ('owner', 'co_host', 'moderator', 'player', 'observer');
+
+-- Every member so far was invited as a co-host
+alter table game_members
+    add role game_role default 'co_host' not null
+        constraint game_members_role_check
+            check (role in ('co_host', 'moderator'));
+
+alter table game_members
+    alter role drop default;
+
+comment on table game_members is 'Registered users invited to help run a game, the owner and players are not listed';
@@ -0,0 +1,13 @@
+-- -----------------------------------------------------------------------------
+-- Let players mark themselves ready before the game starts
+-- -----------------------------------------------------------------------------
+
+alter table games
+    add min_players integer default 1 not null;
+
+comment on column games.min_players is 'Ready players needed before the host can start the game without forcing it';
+
+alter table players
+    add ready boolean default false not null;
+
+comment on column players.ready is 'Whether the player is ready for the game to start';
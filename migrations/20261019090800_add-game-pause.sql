@@ -0,0 +1,13 @@
+-- -----------------------------------------------------------------------------
+-- Allow the host to pause and resume a running game
+-- -----------------------------------------------------------------------------
+
+alter table games
+    add paused boolean default false not null;
+
+comment on column games.paused is 'Whether the host has paused the game';
+
+alter table rounds
+    add reveal_suspended boolean default false not null;
+
+comment on column rounds.reveal_suspended is 'Whether revealing the answers was suspended by a pause and should continue on resume';
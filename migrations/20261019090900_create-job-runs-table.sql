@@ -0,0 +1,23 @@
+-- -----------------------------------------------------------------------------
+-- Create job runs table
+-- -----------------------------------------------------------------------------
+
+create type job_run_status as enum ('running', 'succeeded', 'failed');
+
+create table job_runs
+(
+    id       uuid           default gen_random_uuid()          not null
+        constraint job_runs_pk
+            primary key,
+    job_name varchar                                            not null,
+    status   job_run_status default 'running'::job_run_status not null,
+    error    text,
+    started  timestamp      default now()                      not null,
+    finished timestamp
+);
+
+comment on table job_runs is 'History of the background jobs run by the scheduler';
+comment on column job_runs.error is 'Error the run failed with, if any';
+
+create index job_runs_job_name_started_index
+    on job_runs (job_name, started desc);
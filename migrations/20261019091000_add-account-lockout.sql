@@ -0,0 +1,45 @@
+-- -----------------------------------------------------------------------------
+-- Track failed logins, lock accounts that keep failing and audit every attempt
+-- -----------------------------------------------------------------------------
+
+alter table users
+    add failed_logins integer default 0 not null,
+    add locked_until  timestamp;
+
+comment on column users.failed_logins is 'Failed logins since the last successful one';
+comment on column users.locked_until is 'Logins are refused until then, after too many failures';
+
+create type auth_event_kind as enum (
+    'registered',
+    'login_succeeded',
+    'login_failed',
+    'login_refused_locked',
+    'account_locked',
+    'rate_limited',
+    'logged_out'
+    );
+
+create table auth_events
+(
+    id      uuid      default gen_random_uuid() not null
+        constraint auth_events_pk
+            primary key,
+    user_id uuid
+        constraint auth_events_users_id_fk
+            references users
+            on delete set null,
+    email   varchar                             not null,
+    kind    auth_event_kind                     not null,
+    ip      varchar,
+    created timestamp default now()             not null
+);
+
+comment on table auth_events is 'Audit log of logins, registrations and logouts';
+comment on column auth_events.user_id is 'The account, unset when the email matched none or it was deleted';
+comment on column auth_events.email is 'The email that was tried, as given';
+
+create index auth_events_email_created_index
+    on auth_events (email, created desc);
+
+create index auth_events_user_id_created_index
+    on auth_events (user_id, created desc);
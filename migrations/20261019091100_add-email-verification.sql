@@ -0,0 +1,17 @@
+-- -----------------------------------------------------------------------------
+-- Verify the email of new accounts and let users reset a forgotten password
+-- -----------------------------------------------------------------------------
+
+alter table users
+    add email_verified timestamp;
+
+comment on column users.email_verified is 'When the user proved they own the email, logins are refused until then';
+
+-- Accounts made before verification existed are trusted as they are
+update users
+set email_verified = created;
+
+alter type auth_event_kind add value 'login_refused_unverified';
+alter type auth_event_kind add value 'email_verified';
+alter type auth_event_kind add value 'password_reset_requested';
+alter type auth_event_kind add value 'password_reset';
@@ -0,0 +1,31 @@
+-- -----------------------------------------------------------------------------
+-- Link accounts to the identities they log in with through OpenID Connect
+-- -----------------------------------------------------------------------------
+
+create table user_identities
+(
+    id      uuid      default gen_random_uuid() not null
+        constraint user_identities_pk
+            primary key,
+    user_id uuid                                not null
+        constraint user_identities_users_id_fk
+            references users
+            on delete cascade,
+    issuer  varchar                             not null,
+    subject varchar                             not null,
+    email   varchar,
+    created timestamp default now()             not null,
+    constraint user_identities_issuer_subject_key
+        unique (issuer, subject)
+);
+
+comment on table user_identities is 'Identities at OpenID Connect providers that log in to an account';
+comment on column user_identities.issuer is 'The provider, as the iss claim of its ID tokens';
+comment on column user_identities.subject is 'The identity at the provider, as the sub claim of its ID tokens';
+comment on column user_identities.email is 'The email the provider gave when the identity was linked';
+
+create index user_identities_user_id_index
+    on user_identities (user_id);
+
+alter type auth_event_kind add value 'identity_linked';
+alter type auth_event_kind add value 'login_refused_no_account';
@@ -0,0 +1,8 @@
+-- -----------------------------------------------------------------------------
+-- Let reveal sequences lapse when the process running them goes away
+-- -----------------------------------------------------------------------------
+
+alter table rounds
+    add reveal_lease_until timestamp;
+
+comment on column rounds.reveal_lease_until is 'When the running reveal sequence is considered gone unless it renews its lease';
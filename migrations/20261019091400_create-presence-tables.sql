@@ -0,0 +1,30 @@
+-- -----------------------------------------------------------------------------
+-- Record which players and hosts are connected, so that every instance of the
+-- server can tell
+-- -----------------------------------------------------------------------------
+
+create table player_presence
+(
+    player_id uuid                    not null
+        constraint player_presence_pk
+            primary key
+        constraint player_presence_players_id_fk
+            references players
+            on delete cascade,
+    seen      timestamp default now() not null
+);
+
+comment on table player_presence is 'When a server last had a websocket open for the player';
+
+create table host_presence
+(
+    game_id uuid                    not null
+        constraint host_presence_pk
+            primary key
+        constraint host_presence_games_id_fk
+            references games
+            on delete cascade,
+    seen    timestamp default now() not null
+);
+
+comment on table host_presence is 'When a server last had a websocket open for a host of the game';
@@ -0,0 +1,5 @@
+-- -----------------------------------------------------------------------------
+-- Audit attempts to register an email that already has an account
+-- -----------------------------------------------------------------------------
+
+alter type auth_event_kind add value 'register_refused_existing';
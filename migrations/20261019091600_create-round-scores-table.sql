@@ -0,0 +1,28 @@
+-- -----------------------------------------------------------------------------
+-- Create round scores table
+-- -----------------------------------------------------------------------------
+
+create table round_scores
+(
+    round_id  uuid                    not null
+        constraint round_scores_rounds_id_fk
+            references rounds
+            on delete cascade,
+    player_id uuid                    not null
+        constraint round_scores_players_id_fk
+            references players
+            on delete cascade,
+    points    int       default 0     not null,
+    created   timestamp default now() not null,
+    updated   timestamp default now() not null,
+    constraint round_scores_pk
+        primary key (round_id, player_id)
+);
+
+comment on table round_scores is 'Points each player was awarded in a round, kept so they can be hidden from players until the round ends';
+
+create trigger update_round_scores_updated
+    before update
+    on round_scores
+    for each row
+execute procedure mod_datetime();
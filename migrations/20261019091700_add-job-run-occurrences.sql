@@ -0,0 +1,11 @@
+-- -----------------------------------------------------------------------------
+-- Let only one instance run each occurrence of a job
+-- -----------------------------------------------------------------------------
+
+alter table job_runs
+    add scheduled_for timestamp;
+
+comment on column job_runs.scheduled_for is 'Occurrence of the schedule the run was for, which only one instance may claim';
+
+create unique index job_runs_job_name_scheduled_for_key
+    on job_runs (job_name, scheduled_for);
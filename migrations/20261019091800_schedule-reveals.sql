@@ -0,0 +1,8 @@
+-- -----------------------------------------------------------------------------
+-- Reveal answers from the job scheduler rather than a task per round
+-- -----------------------------------------------------------------------------
+
+alter table rounds
+    rename reveal_lease_until to next_reveal_at;
+
+comment on column rounds.next_reveal_at is 'When the next answer is revealed while revealing automatically';
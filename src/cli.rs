@@ -0,0 +1,540 @@
+use std::{collections::HashMap, io::Write, path::PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::{distributions::Alphanumeric, Rng};
+use sqlx::{migrate::Migrate, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        games::{Game, GameFilter, GameStatus},
+        users::{NewUser, User, UserFilter, UserUpdate},
+    },
+    repositories::{games::GameRepo, users::UserRepo},
+    services::{auth::AuthService, session::SessionManager},
+    session::SessionStore,
+};
+
+/// Runs the Name That server and the admin tasks
+#[derive(Debug, Parser)]
+#[command(name = "namethat", version)]
+pub struct Cli {
+    /// The config file, `namethat.toml` is used when there is one
+    #[arg(long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+    /// Prints the configuration, with the secrets redacted, and exits
+    #[arg(long, global = true)]
+    pub print_config: bool,
+    /// What to do, serving the app when none is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serves the app
+    Serve,
+    /// Manages the database schema
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommand,
+    },
+    /// Manages the registered users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manages the games
+    Game {
+        #[command(subcommand)]
+        command: GameCommand,
+    },
+    /// Manages the login sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateCommand {
+    /// Applies the pending migrations
+    Run,
+    /// Lists the migrations and whether they have been applied
+    Status,
+    /// Reverts the last applied migration
+    Revert,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Registers a user, generating a password unless one is given
+    Create {
+        email: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Lists the registered users
+    List,
+    /// Deletes a user along with their games, ending their sessions
+    Delete { email: String },
+    /// Sets a new password for a user, generating one unless it is given
+    ResetPassword {
+        email: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Unlocks a user locked out after too many failed logins
+    Unlock { email: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GameCommand {
+    /// Lists the games, optionally only those with a status
+    List {
+        #[arg(long, value_enum)]
+        status: Option<StatusArg>,
+    },
+    /// Finishes a game that is still running
+    Finish { id: Uuid },
+    /// Deletes a game along with its rounds, players and answers
+    Delete { id: Uuid },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SessionsCommand {
+    /// Deletes the expired sessions
+    Cleanup,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum StatusArg {
+    Pending,
+    Started,
+    Finished,
+}
+
+impl From<StatusArg> for GameStatus {
+    fn from(value: StatusArg) -> Self {
+        match value {
+            StatusArg::Pending => GameStatus::Pending,
+            StatusArg::Started => GameStatus::Started,
+            StatusArg::Finished => GameStatus::Finished,
+        }
+    }
+}
+
+/// Runs an admin command against the database, writing what it did to `out`
+///
+/// Serving the app is left to the binary, since it needs the whole config.
+pub async fn run(command: Command, client: PgPool, out: &mut impl Write) -> AppResult<()> {
+    match command {
+        Command::Serve => Err(AppError::InternalError(
+            "The serve command is not an admin command".into(),
+        )),
+        Command::Migrate { command } => migrate(command, &client, out).await,
+        Command::User { command } => {
+            let sessions = SessionManager::new(SessionStore::from_client(client.clone()));
+            user(command, UserRepo::new(client), sessions, out).await
+        }
+        Command::Game { command } => game(command, GameRepo::new(client), out).await,
+        Command::Sessions {
+            command: SessionsCommand::Cleanup,
+        } => {
+            let store = SessionStore::from_client(client);
+            let before = store.count().await?;
+            store.cleanup().await?;
+            let deleted = before - store.count().await?;
+            writeln!(out, "Deleted {} expired sessions", deleted)?;
+            Ok(())
+        }
+    }
+}
+
+async fn migrate(command: MigrateCommand, client: &PgPool, out: &mut impl Write) -> AppResult<()> {
+    let migrator = sqlx::migrate!();
+    let mut connection = client.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied: HashMap<i64, _> = connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+    drop(connection);
+
+    match command {
+        MigrateCommand::Run => {
+            migrator.run(client).await?;
+            let pending = migrator
+                .iter()
+                .filter(|m| !m.migration_type.is_down_migration())
+                .filter(|m| !applied.contains_key(&m.version))
+                .count();
+            writeln!(out, "Applied {} migrations", pending)?;
+        }
+        MigrateCommand::Status => {
+            for migration in migrator
+                .iter()
+                .filter(|m| !m.migration_type.is_down_migration())
+            {
+                let status = match applied.get(&migration.version) {
+                    Some(checksum) if *checksum != migration.checksum => "changed",
+                    Some(_) => "applied",
+                    None => "pending",
+                };
+                writeln!(
+                    out,
+                    "{:<8} {} {}",
+                    status, migration.version, migration.description
+                )?;
+            }
+        }
+        MigrateCommand::Revert => {
+            let mut versions: Vec<i64> = applied.keys().copied().collect();
+            versions.sort();
+            let last = versions.pop().ok_or(AppError::ValidationError(
+                "No migrations are applied".into(),
+            ))?;
+            let revertible = migrator
+                .iter()
+                .any(|m| m.version == last && m.migration_type.is_down_migration());
+            if !revertible {
+                return Err(AppError::ValidationError(format!(
+                    "Migration {} has no down migration and cannot be reverted",
+                    last
+                )));
+            }
+
+            migrator.undo(client, versions.pop().unwrap_or(0)).await?;
+            writeln!(out, "Reverted migration {}", last)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn user(
+    command: UserCommand,
+    user_repo: UserRepo,
+    sessions: SessionManager,
+    out: &mut impl Write,
+) -> AppResult<()> {
+    match command {
+        UserCommand::Create { email, password } => {
+            AuthService::validate_email(&email)?;
+            if user_repo.get_by_email(email.clone()).await?.is_some() {
+                return Err(AppError::ValidationError("Email already in use".into()));
+            }
+
+            let password = password_or_generated(password, out)?;
+            let user = user_repo
+                .insert(NewUser {
+                    email,
+                    password: AuthService::hash_password(&password)?,
+                })
+                .await?;
+            // Accounts made by an admin are trusted with the email they gave
+            user_repo.mark_email_verified(user.id).await?;
+            writeln!(out, "Created user {} ({})", user.email, user.id)?;
+        }
+        UserCommand::List => {
+            for user in user_repo.list(UserFilter { email: None }).await? {
+                writeln!(out, "{} {}", user.id, user.email)?;
+            }
+        }
+        UserCommand::Delete { email } => {
+            let user = find_user(&user_repo, email).await?;
+            let ended = sessions.destroy_user_sessions(&user.id).await?;
+            user_repo.delete(user.id).await?;
+            writeln!(
+                out,
+                "Deleted user {} ({}) and ended {} sessions",
+                user.email, user.id, ended
+            )?;
+        }
+        UserCommand::ResetPassword { email, password } => {
+            let user = find_user(&user_repo, email).await?;
+            let password = password_or_generated(password, out)?;
+            user_repo
+                .update(
+                    user.id,
+                    UserUpdate {
+                        email: user.email.clone(),
+                        password: AuthService::hash_password(&password)?,
+                    },
+                )
+                .await?;
+            let ended = sessions.destroy_user_sessions(&user.id).await?;
+            writeln!(
+                out,
+                "Reset the password of {} and ended {} sessions",
+                user.email, ended
+            )?;
+        }
+        UserCommand::Unlock { email } => {
+            let user = find_user(&user_repo, email).await?;
+            user_repo.reset_failed_logins(user.id).await?;
+            writeln!(out, "Unlocked {}", user.email)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn game(command: GameCommand, game_repo: GameRepo, out: &mut impl Write) -> AppResult<()> {
+    match command {
+        GameCommand::List { status } => {
+            let games = game_repo
+                .list(GameFilter {
+                    user_id: None,
+                    status: status.map(GameStatus::from),
+                })
+                .await?;
+            for game in games {
+                writeln!(
+                    out,
+                    "{} {:<9} {} players {}",
+                    game.id,
+                    format!("{:?}", game.status).to_lowercase(),
+                    game.players.len(),
+                    game.name
+                )?;
+            }
+        }
+        GameCommand::Finish { id } => {
+            let game = find_game(&game_repo, &id).await?;
+            if game.status == GameStatus::Finished {
+                return Err(AppError::ValidationError("Game is already finished".into()));
+            }
+            game_repo.end(&id).await?;
+            writeln!(out, "Finished game {}", game.name)?;
+        }
+        GameCommand::Delete { id } => {
+            let game = find_game(&game_repo, &id).await?;
+            game_repo.delete(id).await?;
+            writeln!(out, "Deleted game {}", game.name)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn find_user(user_repo: &UserRepo, email: String) -> AppResult<User> {
+    user_repo
+        .get_by_email(email.clone())
+        .await?
+        .ok_or(AppError::NotFoundError(format!(
+            "No user with email {}",
+            email
+        )))
+}
+
+async fn find_game(game_repo: &GameRepo, id: &Uuid) -> AppResult<Game> {
+    game_repo.get(id).await.map_err(|e| match e {
+        AppError::NotFoundError(_) => AppError::NotFoundError(format!("No game with id {}", id)),
+        e => e,
+    })
+}
+
+/// Uses the given password, or generates one and prints it since nobody will
+/// know it otherwise
+fn password_or_generated(password: Option<String>, out: &mut impl Write) -> AppResult<String> {
+    match password {
+        Some(password) => Ok(password),
+        None => {
+            let password: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(20)
+                .map(char::from)
+                .collect();
+            writeln!(out, "Generated password: {}", password)?;
+            Ok(password)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_session::{Session, SessionStore as _};
+
+    use crate::{
+        models::games::{GameMode, LateJoin, NewGame},
+        services::auth::Lockout,
+    };
+
+    use super::*;
+
+    /// Connects to the database named by `TEST_DATABASE_URL`
+    async fn test_client() -> PgPool {
+        dotenv::dotenv().ok();
+        let url = std::env::var("TEST_DATABASE_URL").expect("Did not find database URL");
+        let client = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!().run(&client).await.unwrap();
+        client
+    }
+
+    async fn run_args(args: &[&str], client: &PgPool) -> AppResult<String> {
+        let cli = Cli::try_parse_from(["namethat"].iter().chain(args)).unwrap();
+        let mut out = vec![];
+        run(cli.command.unwrap(), client.clone(), &mut out).await?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn unique_email() -> String {
+        format!("cli-{}@example.com", Uuid::from_u128(rand::random()))
+    }
+
+    #[test]
+    fn no_subcommand_means_serve() {
+        let cli = Cli::try_parse_from(["namethat", "--config", "prod.toml"]).unwrap();
+
+        assert!(cli.command.is_none());
+        assert_eq!(cli.config, Some(PathBuf::from("prod.toml")));
+    }
+
+    #[test]
+    fn game_ids_must_be_uuids() {
+        assert!(Cli::try_parse_from(["namethat", "game", "finish", "42"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn users_can_be_created_listed_and_deleted() {
+        let client = test_client().await;
+        let email = unique_email();
+
+        let created = run_args(&["user", "create", &email], &client)
+            .await
+            .unwrap();
+        assert!(created.contains("Generated password: "));
+        let listed = run_args(&["user", "list"], &client).await.unwrap();
+        assert!(listed.contains(&email));
+        let user = UserRepo::new(client.clone())
+            .get_by_email(email.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let store = SessionStore::from_client(client.clone());
+        let mut session = Session::new();
+        session.insert("user_id", user.id).unwrap();
+        let session_id = session.id().to_owned();
+        store.store_session(session).await.unwrap();
+        run_args(&["user", "delete", &email], &client)
+            .await
+            .unwrap();
+
+        let listed = run_args(&["user", "list"], &client).await.unwrap();
+        assert!(!listed.contains(&email));
+        assert!(store.load_by_id(&session_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reset_password_replaces_the_hash() {
+        let client = test_client().await;
+        let email = unique_email();
+        run_args(&["user", "create", &email, "--password", "old"], &client)
+            .await
+            .unwrap();
+        let user_repo = UserRepo::new(client.clone());
+        let user = user_repo
+            .get_by_email(email.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let store = SessionStore::from_client(client.clone());
+        let mut session = Session::new();
+        session.insert("user_id", user.id).unwrap();
+        let session_id = session.id().to_owned();
+        store.store_session(session).await.unwrap();
+
+        run_args(
+            &["user", "reset-password", &email, "--password", "new"],
+            &client,
+        )
+        .await
+        .unwrap();
+
+        let user = user_repo.get_by_email(email).await.unwrap().unwrap();
+        assert!(AuthService::check_password("new", &user.password).unwrap());
+        assert!(store.load_by_id(&session_id).await.unwrap().is_none());
+        user_repo.delete(user.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unlock_ends_a_lockout() {
+        let client = test_client().await;
+        let email = unique_email();
+        run_args(&["user", "create", &email], &client)
+            .await
+            .unwrap();
+        let user_repo = UserRepo::new(client.clone());
+        let user = user_repo
+            .get_by_email(email.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let lockout = Lockout {
+            max_failures: 2,
+            base: Duration::from_secs(60),
+            max: Duration::from_secs(60 * 60),
+        };
+
+        let first = user_repo.record_failed_login(user.id, &lockout).await;
+        let second = user_repo.record_failed_login(user.id, &lockout).await;
+        assert_eq!(first.unwrap(), None);
+        assert_eq!(second.unwrap(), Some(Duration::from_secs(60)));
+        assert!(user_repo.is_locked(user.id).await.unwrap());
+
+        run_args(&["user", "unlock", &email], &client)
+            .await
+            .unwrap();
+
+        assert!(!user_repo.is_locked(user.id).await.unwrap());
+        let failures = user_repo.record_failed_login(user.id, &lockout).await;
+        assert_eq!(failures.unwrap(), None);
+        user_repo.delete(user.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn games_can_be_finished() {
+        let client = test_client().await;
+        let email = unique_email();
+        run_args(&["user", "create", &email], &client)
+            .await
+            .unwrap();
+        let user_repo = UserRepo::new(client.clone());
+        let user = user_repo.get_by_email(email).await.unwrap().unwrap();
+        let game_repo = GameRepo::new(client.clone());
+        let game = game_repo
+            .insert(NewGame {
+                user_id: user.id,
+                name: "CLI game".into(),
+                image_urls: vec!["https://example.com/1.png".into()],
+                prompts: vec![],
+                mode: GameMode::Caption,
+                max_answers_per_round: 1,
+                auto_reveal: false,
+                reveal_interval_seconds: 5,
+                answer_tolerance: 0,
+                answer_keys: vec![],
+                late_join: LateJoin::Observer,
+                min_players: 1,
+            })
+            .await
+            .unwrap();
+
+        run_args(&["game", "finish", &game.id.to_string()], &client)
+            .await
+            .unwrap();
+
+        let game = game_repo.get(&game.id).await.unwrap();
+        assert_eq!(game.status, GameStatus::Finished);
+        assert!(run_args(&["game", "finish", &game.id.to_string()], &client)
+            .await
+            .is_err());
+        user_repo.delete(user.id).await.unwrap();
+    }
+}
@@ -15,6 +15,7 @@ pub enum AppError {
     AuthenticationError(String),
     AuthorizationError(String),
     NotFoundError(String),
+    TooManyRequestsError(String),
 }
 
 impl From<sqlx::Error> for AppError {
@@ -68,17 +69,42 @@ impl From<uuid::Error> for AppError {
     }
 }
 
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        AppError::InternalError(format!("{}", error))
+    }
+}
+
 impl From<hyper::Error> for AppError {
     fn from(error: hyper::Error) -> Self {
         AppError::InternalError(format!("{}", error))
     }
 }
 
+impl From<reqwest::Error> for AppError {
+    fn from(error: reqwest::Error) -> Self {
+        AppError::InternalError(format!("{}", error))
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 struct ErrorResponse {
     error: String,
 }
 
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::InternalError(error)
+            | AppError::ValidationError(error)
+            | AppError::AuthenticationError(error)
+            | AppError::AuthorizationError(error)
+            | AppError::NotFoundError(error)
+            | AppError::TooManyRequestsError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, error) = match self {
@@ -87,6 +113,7 @@ impl IntoResponse for AppError {
             AppError::AuthenticationError(error) => (StatusCode::UNAUTHORIZED, error),
             AppError::AuthorizationError(error) => (StatusCode::FORBIDDEN, error),
             AppError::NotFoundError(error) => (StatusCode::NOT_FOUND, error),
+            AppError::TooManyRequestsError(error) => (StatusCode::TOO_MANY_REQUESTS, error),
         };
 
         (status, Json(ErrorResponse { error })).into_response()
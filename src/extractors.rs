@@ -100,3 +100,58 @@ pub mod auth {
         }
     }
 }
+
+pub mod client {
+    use std::{
+        net::{IpAddr, SocketAddr},
+        sync::Arc,
+    };
+
+    use async_trait::async_trait;
+    use axum::{
+        extract::{ConnectInfo, FromRequestParts},
+        http::request::Parts,
+    };
+
+    use crate::{error::AppError, AppState};
+
+    /// The IP address of the caller
+    ///
+    /// Behind a trusted proxy this is the address the proxy appended to
+    /// `X-Forwarded-For`, the earlier entries are set by the caller and can
+    /// not be trusted.
+    #[derive(Debug)]
+    pub struct ClientIp(pub IpAddr);
+
+    #[async_trait]
+    impl FromRequestParts<Arc<AppState>> for ClientIp {
+        type Rejection = AppError;
+
+        async fn from_request_parts(
+            parts: &mut Parts,
+            state: &Arc<AppState>,
+        ) -> Result<Self, Self::Rejection> {
+            if state.trust_forwarded_for {
+                let forwarded = parts
+                    .headers
+                    .get_all("x-forwarded-for")
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .last()
+                    .and_then(|ip| ip.trim().parse().ok());
+                if let Some(ip) = forwarded {
+                    return Ok(ClientIp(ip));
+                }
+            }
+
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| ClientIp(addr.ip()))
+                .ok_or(AppError::InternalError(
+                    "Could not find the client address".into(),
+                ))
+        }
+    }
+}
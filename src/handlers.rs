@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use axum::{
     body::Body,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 
@@ -10,6 +10,7 @@ use crate::AppState;
 
 pub mod auth;
 pub mod games;
+pub mod health;
 pub mod home;
 pub mod profile;
 pub mod websocket;
@@ -20,6 +21,9 @@ impl AppRouter {
     pub fn build() -> Router<Arc<AppState>, Body> {
         Router::new()
             .route("/", get(home::index))
+            .route("/healthz", get(health::healthz))
+            .route("/readyz", get(health::readyz))
+            .route("/metrics", get(health::metrics))
             .route("/401", get(home::unauthorized))
             .route("/403", get(home::forbidden))
             .route("/404", get(home::not_found))
@@ -30,6 +34,14 @@ impl AppRouter {
             .route("/register", get(auth::show_register_form))
             .route("/api/register", post(auth::register))
             .route("/logout", get(auth::logout))
+            .route("/verify-email", get(auth::verify_email))
+            .route("/api/verify-email/resend", post(auth::resend_verification))
+            .route("/forgot-password", get(auth::show_forgot_password_form))
+            .route("/api/forgot-password", post(auth::forgot_password))
+            .route("/reset-password", get(auth::show_reset_password_form))
+            .route("/api/reset-password", post(auth::reset_password))
+            .route("/auth/oidc/login", get(auth::oidc_login))
+            .route("/auth/oidc/callback", get(auth::oidc_callback))
             .route("/games", get(games::games_page))
             .route("/games/create", get(games::create_page))
             .route("/games/:id/run", get(games::run_page))
@@ -40,6 +52,11 @@ impl AppRouter {
                 "/api/games/:id",
                 get(games::get).put(games::update).delete(games::delete),
             )
+            .route("/api/games/:id/members", post(games::invite_member))
+            .route(
+                "/api/games/:id/members/:user_id",
+                delete(games::remove_member),
+            )
             .route("/profile", get(profile::show_profile))
             .route(
                 "/api/profile",
@@ -47,6 +64,7 @@ impl AppRouter {
                     .put(profile::update)
                     .delete(profile::delete),
             )
+            .route("/api/profile/identities", get(profile::identities))
             .route("/assets/:path", get(home::static_handler))
     }
 }
@@ -1,27 +1,41 @@
-use std::sync::Arc;
+use std::{future::Future, net::IpAddr, sync::Arc};
 
 use axum::{
-    extract::State,
-    response::{IntoResponse, Redirect},
+    extract::{Query, State},
+    response::{IntoResponse, Redirect, Response},
     Json,
 };
 use axum_sessions::extractors::WritableSession;
 use serde::Deserialize;
 use serde_json::json;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::users::NewUser,
-    services::auth::AuthService,
+    extractors::client::ClientIp,
+    models::{
+        audit::{AuthEventKind, NewAuthEvent},
+        users::{NewUser, User, UserUpdate},
+    },
+    services::{
+        auth::AuthService,
+        oidc::{Account, Callback, OidcLogin, PendingLogin},
+        tokens::{TokenPurpose, TokenSigner},
+    },
     view, AppState,
 };
 
-pub async fn show_login_form(session: WritableSession) -> AppResult<impl IntoResponse> {
+pub async fn show_login_form(
+    State(state): State<Arc<AppState>>,
+    session: WritableSession,
+) -> AppResult<impl IntoResponse> {
     if is_logged_in(&session) {
         Ok(Redirect::to("/games").into_response())
     } else {
-        Ok(view::Login::new().to_html()?.into_response())
+        Ok(view::Login::new(provider_name(&state))
+            .to_html()?
+            .into_response())
     }
 }
 
@@ -41,55 +55,472 @@ pub struct Login {
 
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
     mut session: WritableSession,
     Json(data): Json<Login>,
 ) -> AppResult<impl IntoResponse> {
     if is_logged_in(&session) {
         return Err(AppError::ValidationError("Already logged in".to_string()));
     }
+    let attempt = Attempt {
+        state: &state,
+        email: &data.email,
+        ip,
+    };
+    if let Err(e) = state.auth_limits.check(ip, &data.email) {
+        attempt.record(AuthEventKind::RateLimited, None).await?;
+        return Err(e);
+    }
 
-    let user = state
-        .user_repo
-        .get_by_email(data.email)
-        .await?
-        .ok_or(AppError::NotFoundError("User not found".to_string()))?;
+    // Every failure looks the same to the caller, so that logging in does
+    // not tell which emails have an account
+    let invalid = || AppError::AuthenticationError("Invalid email or password".into());
 
-    if AuthService::check_password(&data.password, &user.password)? {
-        session.insert("user_id", user.id).unwrap();
-        Ok(Json(json!({ "success": true })))
-    } else {
-        Err(AppError::AuthorizationError("Invalid password".into()))
+    let user = match state.user_repo.get_by_email(data.email.clone()).await? {
+        Some(user) => user,
+        None => {
+            AuthService::check_without_account(&data.password)?;
+            attempt.record(AuthEventKind::LoginFailed, None).await?;
+            return Err(invalid());
+        }
+    };
+
+    if state.user_repo.is_locked(user.id).await? {
+        attempt
+            .record(AuthEventKind::LoginRefusedLocked, Some(user.id))
+            .await?;
+        return Err(invalid());
+    }
+
+    if !AuthService::check_password(&data.password, &user.password)? {
+        attempt
+            .record(AuthEventKind::LoginFailed, Some(user.id))
+            .await?;
+        let locked = state
+            .user_repo
+            .record_failed_login(user.id, &state.lockout)
+            .await?;
+        if let Some(lock) = locked {
+            tracing::warn!(user_id = %user.id, "Locked account for {:?}", lock);
+            attempt
+                .record(AuthEventKind::AccountLocked, Some(user.id))
+                .await?;
+        }
+        return Err(invalid());
+    }
+
+    state.user_repo.reset_failed_logins(user.id).await?;
+    if !state.user_repo.is_email_verified(user.id).await? {
+        attempt
+            .record(AuthEventKind::LoginRefusedUnverified, Some(user.id))
+            .await?;
+        return Err(AppError::AuthorizationError(
+            "Verify your email before logging in, the link is in the email we sent you".into(),
+        ));
     }
+    attempt
+        .record(AuthEventKind::LoginSucceeded, Some(user.id))
+        .await?;
+    session.insert("user_id", user.id).unwrap();
+    Ok(Json(json!({ "success": true })))
 }
 
+/// Creates an account, which can be logged in to once the email has been
+/// verified
+///
+/// Answers the same when the email already has an account, whose owner is
+/// emailed instead, so that registering does not tell who has an account.
 pub async fn register(
     State(state): State<Arc<AppState>>,
-    mut session: WritableSession,
+    ClientIp(ip): ClientIp,
+    session: WritableSession,
     Json(mut form): Json<NewUser>,
 ) -> AppResult<impl IntoResponse> {
     if is_logged_in(&session) {
         return Err(AppError::ValidationError("Already logged in".into()));
     }
+    let attempt = Attempt {
+        state: &state,
+        email: &form.email,
+        ip,
+    };
+    if let Err(e) = state.auth_limits.check(ip, &form.email) {
+        attempt.record(AuthEventKind::RateLimited, None).await?;
+        return Err(e);
+    }
 
-    let user = state.user_repo.get_by_email(form.email.clone()).await?;
+    AuthService::validate_email(&form.email)?;
+    // Hashed either way, so that how long it takes does not tell either
+    form.password = AuthService::hash_password(&form.password)?;
+    let emails = state.account_emails.clone();
 
-    if user.is_some() {
-        return Err(AppError::ValidationError("Email already in use".into()));
+    match state.user_repo.get_by_email(form.email.clone()).await? {
+        Some(user) => {
+            attempt
+                .record(AuthEventKind::RegisterRefusedExisting, Some(user.id))
+                .await?;
+            send_in_background(async move { emails.send_account_exists(&user).await });
+        }
+        None => {
+            let user = state.user_repo.insert(form.clone()).await?;
+            attempt
+                .record(AuthEventKind::Registered, Some(user.id))
+                .await?;
+            send_in_background(async move { emails.send_verification(&user).await });
+        }
     }
 
-    form.password = AuthService::hash_password(&form.password)?;
+    Ok(Json(json!({ "success": true })))
+}
 
-    let user = state.user_repo.insert(form).await?;
-    session.insert("user_id", user.id).unwrap();
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenQuery {
+    token: String,
+}
+
+pub async fn verify_email(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<TokenQuery>,
+) -> AppResult<impl IntoResponse> {
+    let user = match user_for_token(&state, &query.token, TokenPurpose::VerifyEmail).await {
+        Ok(user) => user,
+        Err(e) => return view::VerifyEmail::new(Some(e.to_string())).to_html(),
+    };
+
+    state.user_repo.mark_email_verified(user.id).await?;
+    let attempt = Attempt {
+        state: &state,
+        email: &user.email,
+        ip,
+    };
+    attempt
+        .record(AuthEventKind::EmailVerified, Some(user.id))
+        .await?;
+
+    view::VerifyEmail::new(None).to_html()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmailRequest {
+    email: String,
+}
+
+/// Sends the verification link again, answering the same whether or not the
+/// email has an account
+pub async fn resend_verification(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(data): Json<EmailRequest>,
+) -> AppResult<impl IntoResponse> {
+    let attempt = Attempt {
+        state: &state,
+        email: &data.email,
+        ip,
+    };
+    if let Err(e) = state.auth_limits.check(ip, &data.email) {
+        attempt.record(AuthEventKind::RateLimited, None).await?;
+        return Err(e);
+    }
+
+    if let Some(user) = state.user_repo.get_by_email(data.email.clone()).await? {
+        if !state.user_repo.is_email_verified(user.id).await? {
+            let emails = state.account_emails.clone();
+            send_in_background(async move { emails.send_verification(&user).await });
+        }
+    }
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn show_forgot_password_form() -> AppResult<impl IntoResponse> {
+    view::ForgotPassword::new().to_html()
+}
+
+/// Sends a password reset link, answering the same whether or not the email
+/// has an account
+pub async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(data): Json<EmailRequest>,
+) -> AppResult<impl IntoResponse> {
+    let attempt = Attempt {
+        state: &state,
+        email: &data.email,
+        ip,
+    };
+    if let Err(e) = state.auth_limits.check(ip, &data.email) {
+        attempt.record(AuthEventKind::RateLimited, None).await?;
+        return Err(e);
+    }
+
+    let user = state.user_repo.get_by_email(data.email.clone()).await?;
+    attempt
+        .record(
+            AuthEventKind::PasswordResetRequested,
+            user.as_ref().map(|u| u.id),
+        )
+        .await?;
+    if let Some(user) = user {
+        let emails = state.account_emails.clone();
+        send_in_background(async move { emails.send_password_reset(&user).await });
+    }
 
     Ok(Json(json!({ "success": true })))
 }
 
-pub async fn logout(mut session: WritableSession) -> impl IntoResponse {
+pub async fn show_reset_password_form(
+    Query(query): Query<TokenQuery>,
+) -> AppResult<impl IntoResponse> {
+    view::ResetPassword::new(query.token).to_html()
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ResetPassword {
+    token: String,
+    password: String,
+}
+
+/// Sets the new password and logs the user out everywhere, since whoever
+/// knew the old password may be logged in
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(data): Json<ResetPassword>,
+) -> AppResult<impl IntoResponse> {
+    if data.password.is_empty() {
+        return Err(AppError::ValidationError("Enter a new password".into()));
+    }
+    let user = user_for_token(&state, &data.token, TokenPurpose::ResetPassword).await?;
+    let attempt = Attempt {
+        state: &state,
+        email: &user.email,
+        ip,
+    };
+    if let Err(e) = state.auth_limits.check(ip, &user.email) {
+        attempt
+            .record(AuthEventKind::RateLimited, Some(user.id))
+            .await?;
+        return Err(e);
+    }
+
+    state
+        .user_repo
+        .update(
+            user.id,
+            UserUpdate {
+                email: user.email.clone(),
+                password: AuthService::hash_password(&data.password)?,
+            },
+        )
+        .await?;
+    state.user_repo.reset_failed_logins(user.id).await?;
+    // The link was sent to the email, so following it proves the user owns it
+    state.user_repo.mark_email_verified(user.id).await?;
+    let sessions = state
+        .session_manager
+        .destroy_user_sessions(&user.id)
+        .await?;
+    tracing::info!(user_id = %user.id, "Reset password and ended {} sessions", sessions);
+    attempt
+        .record(AuthEventKind::PasswordReset, Some(user.id))
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+/// The user a token from an emailed link was signed for
+async fn user_for_token(state: &AppState, token: &str, purpose: TokenPurpose) -> AppResult<User> {
+    let user_id = TokenSigner::user_id(token)?;
+    let user = match state.user_repo.get(user_id).await {
+        Ok(user) => user,
+        Err(AppError::NotFoundError(_)) => {
+            return Err(AppError::ValidationError("This link is not valid".into()))
+        }
+        Err(e) => return Err(e),
+    };
+
+    state.account_emails.verify(token, purpose, &user)?;
+    Ok(user)
+}
+
+/// Sends an email without waiting for it, so that neither how long sending
+/// takes nor whether it fails tells the caller that the email has an account
+fn send_in_background(send: impl Future<Output = AppResult<()>> + Send + 'static) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = send.await {
+                tracing::error!("Could not send account email: {:?}", e);
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    mut session: WritableSession,
+) -> AppResult<impl IntoResponse> {
+    if let Some(user_id) = session.get::<Uuid>("user_id") {
+        if let Ok(user) = state.user_repo.get(user_id).await {
+            let attempt = Attempt {
+                state: &state,
+                email: &user.email,
+                ip,
+            };
+            attempt
+                .record(AuthEventKind::LoggedOut, Some(user.id))
+                .await?;
+        }
+    }
+
     session.destroy();
-    Redirect::to("/")
+    Ok(Redirect::to("/"))
+}
+
+/// Where the login in progress at the OpenID Connect provider is kept in the
+/// session
+const PENDING_OIDC_LOGIN: &str = "oidc_login";
+
+/// Sends the user to the OpenID Connect provider to log in, or to link their
+/// identity there to their account when they are logged in already
+pub async fn oidc_login(
+    State(state): State<Arc<AppState>>,
+    mut session: WritableSession,
+) -> AppResult<impl IntoResponse> {
+    let oidc = oidc(&state)?;
+    let (url, pending) = oidc.client.authorize().await?;
+
+    session.insert(PENDING_OIDC_LOGIN, pending)?;
+    Ok(Redirect::to(&url))
+}
+
+/// Where the OpenID Connect provider sends the user back to, which logs them
+/// in to the account linked to their identity
+///
+/// Failures are shown on the login page, since the user got here from the
+/// provider rather than from a form of the app.
+pub async fn oidc_callback(
+    State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    mut session: WritableSession,
+    Query(callback): Query<Callback>,
+) -> AppResult<Response> {
+    let oidc = oidc(&state)?;
+    let logged_in = session.get::<Uuid>("user_id");
+    let pending = session.get::<PendingLogin>(PENDING_OIDC_LOGIN);
+    session.remove(PENDING_OIDC_LOGIN);
+
+    let result = match pending {
+        Some(pending) => finish_oidc_login(&state, oidc, ip, &pending, callback, logged_in).await,
+        None => Err(AppError::AuthenticationError(
+            "The login expired, try again".into(),
+        )),
+    };
+    let user = match result {
+        Ok(user) => user,
+        Err(AppError::InternalError(e)) => return Err(AppError::InternalError(e)),
+        Err(e) => {
+            return Ok(view::Login::new(provider_name(&state))
+                .with_error(e.to_string())
+                .to_html()?
+                .into_response())
+        }
+    };
+
+    match logged_in {
+        Some(_) => Ok(Redirect::to("/profile").into_response()),
+        None => {
+            session.insert("user_id", user.id)?;
+            Ok(Redirect::to("/games").into_response())
+        }
+    }
+}
+
+/// Checks the identity the provider vouched for and finds its account,
+/// recording what happened in the audit log
+async fn finish_oidc_login(
+    state: &AppState,
+    oidc: &OidcLogin,
+    ip: IpAddr,
+    pending: &PendingLogin,
+    callback: Callback,
+    logged_in: Option<Uuid>,
+) -> AppResult<User> {
+    let claims = oidc.client.finish(pending, callback).await?;
+    let attempt = Attempt {
+        state,
+        email: claims.email.as_deref().unwrap_or(&claims.sub),
+        ip,
+    };
+
+    let account = oidc
+        .account(&state.user_repo, &state.identity_repo, &claims, logged_in)
+        .await?;
+    let Some(account) = account else {
+        attempt
+            .record(AuthEventKind::LoginRefusedNoAccount, None)
+            .await?;
+        return Err(AppError::AuthorizationError(format!(
+            "No account is linked to your {} identity",
+            oidc.provider_name
+        )));
+    };
+
+    let user_id = account.user().id;
+    if let Account::Created(_) = account {
+        attempt
+            .record(AuthEventKind::Registered, Some(user_id))
+            .await?;
+    }
+    if let Account::Linked(_) | Account::Created(_) = account {
+        tracing::info!(user_id = %user_id, issuer = %claims.iss, "Linked identity");
+        attempt
+            .record(AuthEventKind::IdentityLinked, Some(user_id))
+            .await?;
+    }
+    if logged_in.is_none() {
+        attempt
+            .record(AuthEventKind::LoginSucceeded, Some(user_id))
+            .await?;
+    }
+
+    Ok(account.user().clone())
+}
+
+fn oidc(state: &AppState) -> AppResult<&OidcLogin> {
+    state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| AppError::NotFoundError("Single sign-on is not enabled".into()))
+}
+
+fn provider_name(state: &AppState) -> Option<String> {
+    state.oidc.as_ref().map(|oidc| oidc.provider_name.clone())
 }
 
 fn is_logged_in(session: &WritableSession) -> bool {
     session.get::<Uuid>("user_id").is_some()
 }
+
+/// An attempt to authenticate, recorded in the audit log
+struct Attempt<'a> {
+    state: &'a AppState,
+    email: &'a str,
+    ip: IpAddr,
+}
+
+impl Attempt<'_> {
+    async fn record(&self, kind: AuthEventKind, user_id: Option<Uuid>) -> AppResult<()> {
+        self.state
+            .audit_repo
+            .record(NewAuthEvent {
+                user_id,
+                email: self.email.to_owned(),
+                kind,
+                ip: Some(self.ip),
+            })
+            .await
+    }
+}
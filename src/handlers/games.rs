@@ -11,7 +11,13 @@ use uuid::Uuid;
 use crate::{
     error::{AppError, AppResult},
     extractors::auth::{ApiAuth, AuthUser, WebAuth},
-    models::games::{GameFilter, NewGame, UpdateGame},
+    models::games::{
+        AnswerKey, Game, GameFilter, GameMode, GameRole, GameStatus, LateJoin, NewGame, UpdateGame,
+    },
+    services::{
+        game::{GameActionService, GameBroadcastService},
+        permissions,
+    },
     view::{CreateGame, Games, PlayGame, RunGame},
     AppState,
 };
@@ -31,7 +37,7 @@ pub async fn run_page(
 ) -> AppResult<impl IntoResponse> {
     let game = state.game_repo.get(&game_id).await?;
 
-    if game.user_id != user.id {
+    if !game.is_host(&user.id) {
         return Ok(Redirect::to("/403").into_response());
     }
 
@@ -47,7 +53,7 @@ pub async fn play_page(
     let authenticated = user.is_some();
 
     if let Some(user) = user {
-        if game.user_id == user.id {
+        if game.is_host(&user.id) {
             return Ok(Redirect::to(&format!("/games/{}/run", game_id)).into_response());
         }
     }
@@ -74,12 +80,41 @@ pub async fn create(
     State(state): State<Arc<AppState>>,
     Json(new_game): Json<NewGameRequest>,
 ) -> AppResult<impl IntoResponse> {
+    let max_answers_per_round = new_game.max_answers_per_round.unwrap_or(1);
+    validate_max_answers_per_round(max_answers_per_round)?;
+    let reveal_interval_seconds = new_game.reveal_interval_seconds.unwrap_or(3);
+    validate_reveal_interval_seconds(reveal_interval_seconds)?;
+    let answer_tolerance = new_game.answer_tolerance.unwrap_or(1);
+    validate_answer_tolerance(answer_tolerance)?;
+    let min_players = new_game.min_players.unwrap_or(1);
+    validate_min_players(min_players)?;
+    let mode = new_game.mode.unwrap_or(GameMode::Caption);
+    let prompts = new_game.prompts.unwrap_or_default();
+    let answer_keys = to_answer_keys(new_game.answer_keys.unwrap_or_default());
+    validate_rounds(&mode, &new_game.images, &prompts, &answer_keys)?;
+
+    // Caption games keep the original "Name that" naming, other formats are
+    // named as given.
+    let name = match mode {
+        GameMode::Caption => format!("Name that {}", new_game.name),
+        _ => new_game.name,
+    };
+
     let game = state
         .game_repo
         .insert(NewGame {
             user_id: user.id,
-            name: format!("Name that {}", new_game.name),
+            name,
             image_urls: new_game.images,
+            prompts,
+            mode,
+            max_answers_per_round,
+            auto_reveal: new_game.auto_reveal.unwrap_or(false),
+            reveal_interval_seconds,
+            answer_tolerance,
+            answer_keys,
+            late_join: new_game.late_join.unwrap_or(LateJoin::Observer),
+            min_players,
         })
         .await?;
     Ok(Json(game).into_response())
@@ -114,6 +149,30 @@ pub async fn update(
             "You are not authorized to modify this game".to_string(),
         ));
     }
+    validate_rounds_editable(&game.status, &game_update)?;
+
+    let max_answers_per_round = game_update
+        .max_answers_per_round
+        .unwrap_or(game.max_answers_per_round);
+    validate_max_answers_per_round(max_answers_per_round)?;
+    let reveal_interval_seconds = game_update
+        .reveal_interval_seconds
+        .unwrap_or(game.reveal_interval_seconds);
+    validate_reveal_interval_seconds(reveal_interval_seconds)?;
+    let answer_tolerance = game_update
+        .answer_tolerance
+        .unwrap_or(game.answer_tolerance);
+    validate_answer_tolerance(answer_tolerance)?;
+    let min_players = game_update.min_players.unwrap_or(game.min_players);
+    validate_min_players(min_players)?;
+    let image_urls = game_update.images.unwrap_or(game.image_urls);
+    let prompts = game_update.prompts.unwrap_or(game.prompts);
+    let mode = game_update.mode.unwrap_or(game.mode);
+    let answer_keys = game_update
+        .answer_keys
+        .map(to_answer_keys)
+        .unwrap_or(game.answer_keys);
+    validate_rounds(&mode, &image_urls, &prompts, &answer_keys)?;
 
     let game = state
         .game_repo
@@ -121,7 +180,16 @@ pub async fn update(
             game_id,
             UpdateGame {
                 name: game_update.name.unwrap_or(game.name),
-                image_urls: game_update.images.unwrap_or(game.image_urls),
+                image_urls,
+                prompts,
+                mode,
+                max_answers_per_round,
+                auto_reveal: game_update.auto_reveal.unwrap_or(game.auto_reveal),
+                reveal_interval_seconds,
+                answer_tolerance,
+                answer_keys,
+                late_join: game_update.late_join.unwrap_or(game.late_join),
+                min_players,
             },
         )
         .await?;
@@ -145,14 +213,246 @@ pub async fn delete(
     Ok(Json(game).into_response())
 }
 
+/// Invites another registered user by email to help run the game, as a
+/// co-host or moderator.
+pub async fn invite_member(
+    ApiAuth(user): ApiAuth,
+    State(state): State<Arc<AppState>>,
+    Path(game_id): Path<Uuid>,
+    Json(invite): Json<InviteMemberRequest>,
+) -> AppResult<impl IntoResponse> {
+    let game = state.game_repo.get(&game_id).await?;
+    authorize_member_management(&game, &user.id)?;
+
+    if !matches!(invite.role, GameRole::CoHost | GameRole::Moderator) {
+        return Err(AppError::ValidationError(
+            "Users can only be invited as co-hosts or moderators".into(),
+        ));
+    }
+
+    let member =
+        state
+            .user_repo
+            .get_by_email(invite.email)
+            .await?
+            .ok_or(AppError::ValidationError(
+                "No user is registered with that email".into(),
+            ))?;
+    if member.id == game.user_id {
+        return Err(AppError::ValidationError(
+            "The owner is already running this game".into(),
+        ));
+    }
+
+    let game = state
+        .game_repo
+        .add_member(&game_id, &member.id, invite.role)
+        .await?;
+    Ok(Json(game).into_response())
+}
+
+pub async fn remove_member(
+    ApiAuth(user): ApiAuth,
+    State(state): State<Arc<AppState>>,
+    Path((game_id, member_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<impl IntoResponse> {
+    let game = state.game_repo.get(&game_id).await?;
+    authorize_member_management(&game, &user.id)?;
+
+    let game = state.game_repo.remove_member(&game_id, &member_id).await?;
+    GameBroadcastService::new(game_id, state.game_repo.clone(), state.tx.clone())
+        .broadcast_member_removed(&member_id);
+    Ok(Json(game).into_response())
+}
+
+fn authorize_member_management(game: &Game, user_id: &Uuid) -> AppResult<()> {
+    match game.user_role(user_id) {
+        Some(role) if permissions::can_manage_members(&role) => Ok(()),
+        _ => Err(AppError::AuthorizationError(
+            "You are not authorized to manage who runs this game".to_string(),
+        )),
+    }
+}
+
+fn validate_max_answers_per_round(max_answers_per_round: i32) -> AppResult<()> {
+    if max_answers_per_round < 1 {
+        return Err(AppError::ValidationError(
+            "Players must be allowed at least one answer per round".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_reveal_interval_seconds(reveal_interval_seconds: i32) -> AppResult<()> {
+    if reveal_interval_seconds < 0 {
+        return Err(AppError::ValidationError(
+            "Reveal interval cannot be negative".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_answer_tolerance(answer_tolerance: i32) -> AppResult<()> {
+    if answer_tolerance < 0 {
+        return Err(AppError::ValidationError(
+            "Answer tolerance cannot be negative".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_min_players(min_players: i32) -> AppResult<()> {
+    if min_players < 0 {
+        return Err(AppError::ValidationError(
+            "Minimum players cannot be negative".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validates that the rounds are only changed before the game starts, since
+/// changing them later would invalidate the answers given and scores awarded.
+fn validate_rounds_editable(status: &GameStatus, update: &UpdateGameRequest) -> AppResult<()> {
+    let changes_rounds = update.images.is_some()
+        || update.prompts.is_some()
+        || update.mode.is_some()
+        || update.answer_keys.is_some()
+        || update.max_answers_per_round.is_some();
+    if changes_rounds && *status != GameStatus::Pending {
+        return Err(AppError::ValidationError(
+            "The rounds cannot be changed once the game has started".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validates the prompts and answer keys against the images and the mode of
+/// every round.
+fn validate_rounds(
+    mode: &GameMode,
+    images: &[String],
+    prompts: &[String],
+    answer_keys: &[AnswerKey],
+) -> AppResult<()> {
+    if prompts.len() > images.len() {
+        return Err(AppError::ValidationError(
+            "There are more prompts than images".into(),
+        ));
+    }
+    if answer_keys
+        .iter()
+        .any(|k| k.image_index as usize >= images.len())
+    {
+        return Err(AppError::ValidationError(
+            "There are more answer keys than images".into(),
+        ));
+    }
+
+    (0..images.len()).try_for_each(|i| {
+        let prompt = prompts.get(i).filter(|p| !p.is_empty());
+        let answer_key = answer_keys.iter().find(|k| k.image_index as usize == i);
+        GameActionService::validate_round_mode(mode, prompt.map(|p| p.as_str()), answer_key)
+    })
+}
+
+/// Converts the answer keys given per image into answer keys for the game,
+/// skipping images without an expected answer.
+fn to_answer_keys(answer_keys: Vec<AnswerKeyRequest>) -> Vec<AnswerKey> {
+    answer_keys
+        .into_iter()
+        .enumerate()
+        .filter(|(_, k)| !k.expected_answer.trim().is_empty())
+        .map(|(i, k)| AnswerKey {
+            image_index: i as i32,
+            expected_answer: k.expected_answer.trim().to_owned(),
+            aliases: k
+                .aliases
+                .unwrap_or_default()
+                .into_iter()
+                .map(|a| a.trim().to_owned())
+                .filter(|a| !a.is_empty())
+                .collect(),
+        })
+        .collect()
+}
+
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct NewGameRequest {
     pub name: String,
     pub images: Vec<String>,
+    pub prompts: Option<Vec<String>>,
+    pub mode: Option<GameMode>,
+    pub max_answers_per_round: Option<i32>,
+    pub auto_reveal: Option<bool>,
+    pub reveal_interval_seconds: Option<i32>,
+    pub answer_tolerance: Option<i32>,
+    /// The expected answer for each image, matched to `images` by index
+    pub answer_keys: Option<Vec<AnswerKeyRequest>>,
+    pub late_join: Option<LateJoin>,
+    /// Ready players needed before the game can start without forcing it
+    pub min_players: Option<i32>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UpdateGameRequest {
     pub name: Option<String>,
     pub images: Option<Vec<String>>,
+    pub prompts: Option<Vec<String>>,
+    pub mode: Option<GameMode>,
+    pub max_answers_per_round: Option<i32>,
+    pub auto_reveal: Option<bool>,
+    pub reveal_interval_seconds: Option<i32>,
+    pub answer_tolerance: Option<i32>,
+    /// The expected answer for each image, matched to `images` by index
+    pub answer_keys: Option<Vec<AnswerKeyRequest>>,
+    pub late_join: Option<LateJoin>,
+    /// Ready players needed before the game can start without forcing it
+    pub min_players: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnswerKeyRequest {
+    pub expected_answer: String,
+    pub aliases: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct InviteMemberRequest {
+    pub email: String,
+    pub role: GameRole,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(json: &str) -> UpdateGameRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn rounds_cannot_be_changed_once_the_game_has_started() {
+        let changes = [
+            r#"{"images": ["one"]}"#,
+            r#"{"prompts": ["Name that"]}"#,
+            r#"{"mode": "guessTheAnswer"}"#,
+            r#"{"answerKeys": [{"expectedAnswer": "cat"}]}"#,
+            r#"{"maxAnswersPerRound": 2}"#,
+        ];
+
+        for change in changes {
+            assert!(validate_rounds_editable(&GameStatus::Pending, &update(change)).is_ok());
+            assert!(matches!(
+                validate_rounds_editable(&GameStatus::Started, &update(change)),
+                Err(AppError::ValidationError(_))
+            ));
+        }
+        assert!(
+            validate_rounds_editable(&GameStatus::Started, &update(r#"{"name": "Renamed"}"#))
+                .is_ok()
+        );
+    }
 }
@@ -0,0 +1,146 @@
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
+};
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{error::AppError, redact::Redacted, AppState};
+
+#[derive(Serialize)]
+struct Readiness {
+    database: String,
+    migrations: String,
+}
+
+/// The bearer token that the metrics are served to
+///
+/// The metrics name the games being played, and a game ID is all it takes to
+/// join a game, so they are only for the monitoring that knows the token.
+#[derive(Clone)]
+pub struct MetricsToken(String);
+
+impl MetricsToken {
+    pub fn new(token: &str) -> Self {
+        Self(token.to_owned())
+    }
+
+    /// Whether the request carries the token in its `Authorization` header
+    fn authorizes(&self, headers: &HeaderMap) -> bool {
+        let Some(given) = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+
+        // Compared in constant time so that the token cannot be guessed
+        // byte by byte from how long the comparison takes
+        given.len() == self.0.len()
+            && given
+                .bytes()
+                .zip(self.0.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl Debug for MetricsToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MetricsToken").field(&Redacted).finish()
+    }
+}
+
+/// Answers as long as the server is running, for liveness probes
+pub async fn healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/// Answers with 200 once the database can be reached and is fully migrated,
+/// for readiness probes, and 503 otherwise
+///
+/// The reason is only given to callers with the metrics token.
+pub async fn readyz(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let database = match state.health_repo.ping().await {
+        Ok(_) => "ok".to_string(),
+        Err(e) => {
+            tracing::warn!("Database is not ready: {:?}", e);
+            "unreachable".to_string()
+        }
+    };
+    let migrations = match state.health_repo.pending_migrations().await {
+        Ok(0) => "ok".to_string(),
+        Ok(pending) => format!("{} pending", pending),
+        Err(e) => {
+            tracing::warn!("Could not check migrations: {:?}", e);
+            "unknown".to_string()
+        }
+    };
+
+    let status = match database == "ok" && migrations == "ok" {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    match &state.metrics_token {
+        Some(token) if token.authorizes(&headers) => (
+            status,
+            Json(Readiness {
+                database,
+                migrations,
+            }),
+        )
+            .into_response(),
+        _ => (status, status.canonical_reason().unwrap_or_default()).into_response(),
+    }
+}
+
+/// Serves the metrics in the Prometheus text format to callers with the
+/// metrics token
+pub async fn metrics(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    match &state.metrics_token {
+        None => Err(AppError::NotFoundError("Metrics are not enabled".into())),
+        Some(token) if !token.authorizes(&headers) => Err(AppError::AuthenticationError(
+            "A valid metrics token is required".into(),
+        )),
+        Some(_) => Ok((
+            [("content-type", "text/plain; version=0.0.4")],
+            state.metrics.render(state.health_repo.pool_usage()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn only_the_metrics_token_is_authorized() {
+        let token = MetricsToken::new("letmescrape");
+
+        assert!(token.authorizes(&headers("Bearer letmescrape")));
+        assert!(!token.authorizes(&headers("Bearer letmescrap")));
+        assert!(!token.authorizes(&headers("Bearer letmescrapeX")));
+        assert!(!token.authorizes(&headers("letmescrape")));
+        assert!(!token.authorizes(&HeaderMap::new()));
+        assert!(!format!("{:?}", token).contains("letmescrape"));
+    }
+}
@@ -26,6 +26,15 @@ pub async fn get(ApiAuth(user): ApiAuth) -> AppResult<impl IntoResponse> {
     Ok(Json(user).into_response())
 }
 
+/// The identities at OpenID Connect providers that log in to the account
+pub async fn identities(
+    ApiAuth(user): ApiAuth,
+    State(state): State<Arc<AppState>>,
+) -> AppResult<impl IntoResponse> {
+    let identities = state.identity_repo.list_for_user(user.id).await?;
+    Ok(Json(identities).into_response())
+}
+
 pub async fn update(
     ApiAuth(user): ApiAuth,
     State(state): State<Arc<AppState>>,
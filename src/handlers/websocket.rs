@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     extract::{
@@ -9,20 +12,101 @@ use axum::{
 };
 use axum_sessions::extractors::WritableSession;
 use futures_util::{stream::SplitStream, StreamExt};
-use tokio::sync::broadcast::Receiver;
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    mpsc,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{field, Instrument, Span};
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
     extractors::auth::AuthUser,
+    metrics::Metrics,
     models::{
-        games::{GameAction, GameBroadcast, GameStatus, PlayerType},
+        games::{
+            GameAction, GameBroadcast, GameMessage, GameRole, GameStatus, LateJoin, NewPlayer,
+            Player, PlayerType,
+        },
         users::User,
     },
-    services::game::{GameActionService, GameBroadcastService, GameMessageService},
+    repositories::games::GameRepo,
+    services::{
+        game::{GameActionService, GameBroadcastService, GameMessageService},
+        presence::PresenceTracker,
+        rate_limit::{Rate, TokenBucket},
+    },
     AppState,
 };
 
+/// How often the server pings clients and how long it waits for an answer
+///
+/// Clients answer pings automatically, so a socket that stays silent for
+/// longer than the interval and timeout together is considered dead. This
+/// catches half-open connections, for example from phones that lost signal.
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Heartbeat {
+    /// How long a client may be silent before the socket is torn down
+    pub fn idle_timeout(&self) -> Duration {
+        self.interval + self.timeout
+    }
+}
+
+/// What the receiving half of a socket asks the sending half to tell a client
+/// that sends messages too fast
+#[derive(Debug)]
+enum Throttle {
+    /// Messages are dropped until the client waits this long
+    Dropped(Duration),
+    /// The client kept flooding after being told to slow down
+    Disconnect,
+}
+
+/// What the sending half of a socket needs to know about its connection
+struct SendTaskContext {
+    player_type: PlayerType,
+    user_id: Option<Uuid>,
+    heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+    metrics: Metrics,
+    throttle: mpsc::Receiver<Throttle>,
+}
+
+/// How long clients are asked to wait before reconnecting when the server
+/// shuts down
+const RECONNECT_AFTER: Duration = Duration::from_secs(3);
+
+/// Closes the game sockets when the server shuts down
+///
+/// Sockets are upgraded out of the HTTP server, so they are not drained along
+/// with the requests. Instead each socket tells its client to reconnect
+/// shortly and closes, so that a redeploy looks like a short blip to players
+/// rather than a crash.
+#[derive(Clone, Debug, Default)]
+pub struct SocketShutdown {
+    token: CancellationToken,
+    sockets: TaskTracker,
+}
+
+impl SocketShutdown {
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Closes every socket and waits for them to finish
+    pub async fn close_all(&self) {
+        self.sockets.close();
+        self.token.cancel();
+        self.sockets.wait().await;
+    }
+}
+
 pub async fn game_websocket(
     ws: WebSocketUpgrade,
     AuthUser(user): AuthUser,
@@ -31,12 +115,26 @@ pub async fn game_websocket(
     Path(game_id): Path<Uuid>,
 ) -> crate::error::AppResult<impl IntoResponse> {
     let session_id = session.id().to_string();
+    let sockets = state.shutdown.sockets.clone();
+    // Everything logged for the socket is tagged with the game and, once they
+    // have joined, the player
+    let span = tracing::info_span!(
+        "websocket",
+        %game_id,
+        player_id = field::Empty,
+        role = field::Empty,
+    );
 
-    Ok(ws.on_upgrade(move |socket| async move {
-        match game_handler(socket, user, state, game_id, session_id).await {
-            Ok(_) => (),
-            Err(e) => tracing::error!("Error in websocket handler: {:?}", e),
-        }
+    Ok(ws.on_upgrade(move |socket| {
+        sockets.track_future(
+            async move {
+                match game_handler(socket, user, state, game_id, session_id).await {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!(error = ?e, "Error in websocket handler"),
+                }
+            }
+            .instrument(span),
+        )
     }))
 }
 
@@ -47,6 +145,7 @@ async fn game_handler(
     game_id: Uuid,
     session_id: String,
 ) -> crate::error::AppResult<()> {
+    let open_socket = state.metrics.socket_opened(&game_id);
     let (sender, mut receiver) = socket.split();
     let rx = state.tx.subscribe();
     let tx = state.tx.clone();
@@ -55,7 +154,7 @@ async fn game_handler(
     let mut message_service =
         GameMessageService::new(game_id.clone(), state.game_repo.clone(), sender);
 
-    let player_type = get_player_type(
+    let (player_type, role) = get_player_type(
         &game_id,
         &session_id,
         &state,
@@ -67,109 +166,302 @@ async fn game_handler(
     let player_id = match player_type {
         PlayerType::Player { id, .. } => Some(id),
         PlayerType::Observer { id, .. } => Some(id),
-        PlayerType::GameMaster => None,
+        PlayerType::GameMaster | PlayerType::Moderator => None,
     };
+    let span = Span::current();
+    span.record("role", field::debug(role));
+    if let Some(player_id) = player_id {
+        span.record("player_id", field::display(player_id));
+    }
+
+    // Moderators are not shown to players, so only players and hosts are
+    // tracked. The guard counts the socket as closed however the handler
+    // returns.
+    let tracked = player_type != PlayerType::Moderator;
+    let presence = match tracked {
+        true => Some(state.presence.track(player_id, game_id)),
+        false => None,
+    };
+    if tracked {
+        let host_game_id = player_id.is_none().then_some(game_id);
+        state
+            .game_repo
+            .record_presence(player_id.as_slice(), host_game_id.as_slice())
+            .await?;
+        if host_game_id.is_some() {
+            state.game_repo.mark_host_active(&game_id).await?;
+        }
+    }
 
     message_service.join_success(&player_type).await?;
 
+    let user_id = user.as_ref().map(|u| u.id);
     let game_service = GameActionService::new(
         game_id.clone(),
         state.game_repo.clone(),
+        user_id,
         player_type.clone(),
+        role,
     );
 
-    let mut send_task = get_send_task(rx, game_id, message_service, user);
-    let mut recv_task = get_recv_task(
+    let (throttle_tx, throttle_rx) = mpsc::channel(1);
+    let send_task = get_send_task(
+        rx,
+        message_service,
+        SendTaskContext {
+            player_type: player_type.clone(),
+            user_id,
+            heartbeat: state.heartbeat,
+            shutdown: state.shutdown.token.clone(),
+            metrics: state.metrics.clone(),
+            throttle: throttle_rx,
+        },
+    );
+    let recv_task = get_recv_task(
         receiver,
-        player_type.clone(),
         game_service.clone(),
         broadcast_service.clone(),
+        state.heartbeat,
+        state.metrics.clone(),
+        state.socket_rate,
+        throttle_tx,
     );
 
     // Broadcast the new player message
     broadcast_service.broadcast_new_player(&player_type).await?;
     broadcast_service.broadcast_game_state().await?;
 
-    tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
-    };
+    wait_for_close(send_task, recv_task).await;
+    drop(open_socket);
 
     tracing::info!("Websocket closed");
-    if let Some(player_id) = player_id {
-        tracing::info!("Marking player inactive");
-        state
-            .game_repo
-            .mark_player_inactive(&player_id)
-            .await
-            .map_err(|e| {
-                tracing::error!("marking inactive user");
-                e
-            })?;
-        broadcast_service
-            .broadcast_game_state()
-            .await
-            .map_err(|e| {
-                tracing::error!("sending broadcast of state");
-                e
-            })?;
+    // Players reconnect once the server is back, so they are not shown as
+    // gone while it shuts down
+    if !tracked || state.shutdown.is_shutting_down() {
+        return Ok(());
     }
 
+    drop(presence);
+
+    let gone = release_presence(
+        &state.game_repo,
+        &state.presence,
+        &state.shutdown.token,
+        player_id,
+        game_id,
+    )
+    .await?;
+    if !gone {
+        return Ok(());
+    }
+
+    broadcast_service
+        .broadcast_game_state()
+        .await
+        .map_err(|e| {
+            tracing::error!("sending broadcast of state");
+            e
+        })?;
+
     Ok(())
 }
 
+/// Waits out the grace period after a player or host disconnected, and marks
+/// them inactive unless they reconnected
+///
+/// Giving them a chance to reconnect, to this server or any other, means a
+/// brief connection drop does not make them flicker out. When the server shuts
+/// down during the wait they are left to the inactive sweep, since they may
+/// already be reconnecting elsewhere.
+///
+/// Returns whether they were marked inactive.
+async fn release_presence(
+    game_repo: &GameRepo,
+    presence: &PresenceTracker,
+    shutdown: &CancellationToken,
+    player_id: Option<Uuid>,
+    game_id: Uuid,
+) -> AppResult<bool> {
+    let grace_period = presence.grace_period();
+    tokio::select! {
+        _ = tokio::time::sleep(grace_period) => (),
+        _ = shutdown.cancelled() => {
+            tracing::info!("Shutting down within the grace period");
+            return Ok(false);
+        }
+    }
+
+    let reconnected = match player_id {
+        Some(player_id) => {
+            presence.is_connected(&player_id)
+                || game_repo
+                    .is_player_present(&player_id, grace_period)
+                    .await?
+        }
+        None => {
+            presence.is_host_connected(&game_id)
+                || game_repo.is_host_present(&game_id, grace_period).await?
+        }
+    };
+    if reconnected {
+        tracing::info!("Reconnected within the grace period");
+        return Ok(false);
+    }
+
+    tracing::info!("Marking player inactive");
+    match player_id {
+        Some(player_id) => game_repo.mark_player_inactive(&player_id).await,
+        None => game_repo.mark_host_inactive(&game_id).await,
+    }
+    .map_err(|e| {
+        tracing::error!("marking inactive user");
+        e
+    })?;
+
+    Ok(true)
+}
+
+/// Waits for either side of the socket to finish, and stops the other
+///
+/// The receiving side finishes when the client closes the socket or times
+/// out, the sending side when the client can no longer be reached.
+async fn wait_for_close(
+    mut send_task: tokio::task::JoinHandle<()>,
+    mut recv_task: tokio::task::JoinHandle<()>,
+) {
+    tokio::select! {
+        _ = (&mut send_task) => recv_task.abort(),
+        _ = (&mut recv_task) => send_task.abort(),
+    };
+}
+
 fn get_send_task(
     mut rx: Receiver<GameBroadcast>,
-    game_id: Uuid,
     mut message_service: GameMessageService,
-    user: Option<User>,
+    context: SendTaskContext,
 ) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        while let Ok(broadcast) = rx.recv().await {
-            match handle_broadcast(broadcast.clone(), &game_id, &mut message_service).await {
-                Ok(_) => (),
-                Err(e) => {
-                    tracing::error!(
-                        "Error in broadcast handler: {:?}\nUser: {:#?}\nBroadcast: {:#?}",
-                        e,
-                        user,
-                        broadcast
-                    );
+    let SendTaskContext {
+        player_type,
+        user_id,
+        heartbeat,
+        shutdown,
+        metrics,
+        mut throttle,
+    } = context;
+
+    tokio::spawn(
+        async move {
+            let start = tokio::time::Instant::now() + heartbeat.interval;
+            let mut ping = tokio::time::interval_at(start, heartbeat.interval);
+
+            loop {
+                tokio::select! {
+                    broadcast = rx.recv() => {
+                        let broadcast = match broadcast {
+                            Ok(broadcast) => broadcast,
+                            Err(RecvError::Lagged(skipped)) => {
+                                tracing::warn!("Websocket missed {} broadcasts", skipped);
+                                metrics.broadcast_lagged(skipped);
+                                break;
+                            }
+                            Err(RecvError::Closed) => break,
+                        };
+                        match handle_broadcast(broadcast, &player_type, user_id, &mut message_service)
+                            .await
+                        {
+                            Ok(true) => (),
+                            Ok(false) => break,
+                            Err(e) => tracing::error!(error = ?e, "Error in broadcast handler"),
+                        }
+                    }
+                    _ = ping.tick() => {
+                        if let Err(e) = message_service.ping().await {
+                            tracing::info!("Could not ping websocket: {:?}", e);
+                            break;
+                        }
+                    }
+                    Some(throttle) = throttle.recv() => match throttle {
+                        Throttle::Dropped(retry_after) => {
+                            if let Err(e) = message_service.rate_limited(retry_after).await {
+                                tracing::info!("Could not send to websocket: {:?}", e);
+                                break;
+                            }
+                        }
+                        Throttle::Disconnect => {
+                            tracing::warn!("Disconnecting websocket sending too many messages");
+                            if let Err(e) = message_service.disconnect_flooding().await {
+                                tracing::info!("Could not close websocket: {:?}", e);
+                            }
+                            break;
+                        }
+                    },
+                    _ = shutdown.cancelled() => {
+                        if let Err(e) = message_service.restart(RECONNECT_AFTER).await {
+                            tracing::info!("Could not close websocket: {:?}", e);
+                        }
+                        break;
+                    }
                 }
             }
         }
-    })
+        .instrument(Span::current()),
+    )
 }
 
 fn get_recv_task(
     mut receiver: SplitStream<WebSocket>,
-    player_type: PlayerType,
     recv_game_service: GameActionService,
     broadcast_service: GameBroadcastService,
+    heartbeat: Heartbeat,
+    metrics: Metrics,
+    rate: Rate,
+    throttle: mpsc::Sender<Throttle>,
 ) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        match player_type {
-            PlayerType::Observer { .. } => loop {
-                let message = receiver.next().await;
-                match message {
+    tokio::spawn(
+        async move {
+            let mut bucket = TokenBucket::new(rate, Instant::now());
+            // Messages dropped since the client last sent one within the rate
+            let mut dropped = 0;
+
+            // Observers may only ask to play, which is checked by the game service
+            // since they can be promoted while connected.
+            loop {
+                let message = match next_message(&mut receiver, &heartbeat).await {
                     None | Some(Err(_)) | Some(Ok(Message::Close(_))) => break,
-                    _ => (),
-                }
-            }, // messages from observers are ignored
-            PlayerType::GameMaster | PlayerType::Player { .. } => {
-                while let Some(Ok(Message::Text(message))) = receiver.next().await {
-                    match handle_incoming_message(message, &recv_game_service, &broadcast_service)
+                    Some(Ok(Message::Text(message))) => message,
+                    _ => continue,
+                };
+
+                match bucket.take(Instant::now()) {
+                    Ok(()) => {
+                        dropped = 0;
+                        handle_incoming_message(
+                            message,
+                            &recv_game_service,
+                            &broadcast_service,
+                            &metrics,
+                        )
                         .await
-                    {
-                        Ok(_) => (),
-                        Err(e) => {
-                            tracing::error!("Error in incoming message handler: {:?}", e);
+                    }
+                    Err(retry_after) => {
+                        dropped += 1;
+                        metrics.socket_message_dropped();
+                        // The client is told once, and disconnected if it
+                        // sends another burst without waiting
+                        if dropped == 1 {
+                            let _ = throttle.try_send(Throttle::Dropped(retry_after));
+                        } else if dropped > rate.burst {
+                            let _ = throttle.send(Throttle::Disconnect).await;
+                            // The socket is torn down once the sending half
+                            // has closed it
+                            std::future::pending::<()>().await;
                         }
                     }
                 }
             }
         }
-    })
+        .instrument(Span::current()),
+    )
 }
 
 async fn get_player_type(
@@ -179,14 +471,15 @@ async fn get_player_type(
     user: &Option<User>,
     receiver: &mut SplitStream<WebSocket>,
     message_service: &mut GameMessageService,
-) -> AppResult<PlayerType> {
+) -> AppResult<(PlayerType, GameRole)> {
     let game = state.game_repo.get(game_id).await?;
 
-    // if the user is the game master, use their username
-    if let Some(user) = user {
-        if user.id == game.user_id {
-            return Ok(PlayerType::GameMaster);
-        }
+    // if the user owns or was invited to the game, they help run it
+    if let Some(role) = user.as_ref().and_then(|u| game.user_role(&u.id)) {
+        return match role {
+            GameRole::Moderator => Ok((PlayerType::Moderator, role)),
+            _ => Ok((PlayerType::GameMaster, role)),
+        };
     }
 
     // Check if the user has already joined this game
@@ -198,7 +491,7 @@ async fn get_player_type(
         if let Ok(player) = state.game_repo.get_player(&player_id).await {
             state.game_repo.mark_player_active(&player.id).await?;
             return match game.id == player.game_id {
-                true => Ok(player.to_player_type()),
+                true => Ok(player_with_role(player)),
                 false => Err(AppError::InternalError("Player is not in the game".into())),
             };
         }
@@ -218,7 +511,9 @@ async fn get_player_type(
                     // Get latest version of the game in the case someone else has joined
                     // with the same name
                     let game = state.game_repo.get(game_id).await?;
-                    let observer = game.status != GameStatus::Pending;
+                    let observer =
+                        game.status != GameStatus::Pending && game.late_join == LateJoin::Observer;
+                    let (first_round, score) = game.late_join_terms();
 
                     // If the name is not taken, add the player to the game
                     if name != "Game Master".to_string()
@@ -226,7 +521,13 @@ async fn get_player_type(
                     {
                         let game = state
                             .game_repo
-                            .add_player(&game.id, &name, &observer)
+                            .add_player(NewPlayer {
+                                game_id: game.id,
+                                username: name.clone(),
+                                is_observer: observer,
+                                score,
+                                first_round,
+                            })
                             .await?;
                         let player = game.players.iter().find(|p| p.username == name).ok_or(
                             AppError::InternalError("Player not found after adding to game".into()),
@@ -236,7 +537,7 @@ async fn get_player_type(
                             .set_game_display_name(session_id, &game.id, &player.id)
                             .await?;
 
-                        return Ok(player.clone().to_player_type());
+                        return Ok(player_with_role(player.clone()));
                     }
 
                     // Otherwise, let the user know that the name is taken and wait for
@@ -249,26 +550,375 @@ async fn get_player_type(
     }
 }
 
-/// Determines if a broadcast message should be sent to the user.
+fn player_with_role(player: Player) -> (PlayerType, GameRole) {
+    let role = match player.is_observer {
+        true => GameRole::Observer,
+        false => GameRole::Player,
+    };
+    (player.to_player_type(), role)
+}
+
+/// Waits for the next message from the client
+///
+/// Returns `None` when the client has been silent for longer than the
+/// heartbeat allows, the same as when the socket is closed.
+async fn next_message(
+    receiver: &mut SplitStream<WebSocket>,
+    heartbeat: &Heartbeat,
+) -> Option<Result<Message, axum::Error>> {
+    match tokio::time::timeout(heartbeat.idle_timeout(), receiver.next()).await {
+        Ok(message) => message,
+        Err(_) => {
+            tracing::info!("Websocket timed out");
+            None
+        }
+    }
+}
+
+/// Sends the broadcast to the client if it is for its game
+///
+/// Returns false once the socket should be closed.
 async fn handle_broadcast(
     broadcast: GameBroadcast,
-    game_id: &Uuid,
+    player_type: &PlayerType,
+    user_id: Option<Uuid>,
     message_service: &mut GameMessageService,
-) -> AppResult<()> {
-    if broadcast.game_id == game_id.to_owned() {
-        message_service.send_text(&broadcast.message).await?;
+) -> AppResult<bool> {
+    if broadcast.game_id != *message_service.game_id() {
+        return Ok(true);
+    }
+
+    match broadcast.message {
+        GameMessage::MemberRemoved { user_id: removed } => {
+            let helps_run = matches!(player_type, PlayerType::GameMaster | PlayerType::Moderator);
+            if !helps_run || user_id != Some(removed) {
+                return Ok(true);
+            }
+            tracing::info!("Closing websocket of removed member");
+            message_service.member_removed().await?;
+            Ok(false)
+        }
+        message => {
+            message_service
+                .send(message.for_recipient(player_type))
+                .await?;
+            Ok(true)
+        }
     }
-    Ok(())
 }
 
 async fn handle_incoming_message(
     message: String,
     game_service: &GameActionService,
     broadcast_service: &GameBroadcastService,
-) -> AppResult<()> {
-    let action = serde_json::from_str::<GameAction>(&message)?;
-    game_service.handle_action(&action).await?;
-    broadcast_service.broadcast_action(&action).await?;
+    metrics: &Metrics,
+) {
+    let action = match serde_json::from_str::<GameAction>(&message) {
+        Ok(action) => action,
+        Err(e) => {
+            tracing::error!(error = %e, "Invalid game action");
+            metrics.game_action("invalid", &Err(e.into()));
+            return;
+        }
+    };
 
-    Ok(())
+    let span = tracing::info_span!("game_action", action = action.name());
+    async {
+        let result = match game_service.handle_action(&action).await {
+            Ok(_) => broadcast_service.broadcast_action(&action).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            tracing::error!(error = ?e, "Error handling game action");
+        }
+        metrics.game_action(action.name(), &result);
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Mutex};
+
+    use axum::{extract::ws::close_code, routing::get, Router};
+    use futures_util::SinkExt;
+    use sqlx::PgPool;
+    use tokio::sync::{broadcast, oneshot};
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    use super::*;
+
+    const HEARTBEAT: Heartbeat = Heartbeat {
+        interval: Duration::from_millis(100),
+        timeout: Duration::from_millis(100),
+    };
+
+    const RATE: Rate = Rate {
+        burst: 2,
+        per_second: 1.0,
+    };
+
+    /// The user the game master sockets belong to
+    const USER_ID: Uuid = Uuid::from_u128(1);
+
+    /// Runs the send and receive tasks of a game master socket
+    ///
+    /// The database is never reached since no game messages are exchanged.
+    async fn run_socket(
+        socket: WebSocket,
+        shutdown: CancellationToken,
+        tx: broadcast::Sender<GameBroadcast>,
+    ) {
+        let game_id = Uuid::nil();
+        let game_repo = GameRepo::new(PgPool::connect_lazy("postgres://localhost/unused").unwrap());
+        let rx = tx.subscribe();
+        let (sender, receiver) = socket.split();
+
+        let broadcast_service = GameBroadcastService::new(game_id, game_repo.clone(), tx);
+        let message_service = GameMessageService::new(game_id, game_repo.clone(), sender);
+        let game_service = GameActionService::new(
+            game_id,
+            game_repo,
+            Some(USER_ID),
+            PlayerType::GameMaster,
+            GameRole::Owner,
+        );
+
+        let (throttle_tx, throttle_rx) = mpsc::channel(1);
+
+        let send_task = get_send_task(
+            rx,
+            message_service,
+            SendTaskContext {
+                player_type: PlayerType::GameMaster,
+                user_id: Some(USER_ID),
+                heartbeat: HEARTBEAT,
+                shutdown,
+                metrics: Metrics::new(),
+                throttle: throttle_rx,
+            },
+        );
+        let recv_task = get_recv_task(
+            receiver,
+            game_service,
+            broadcast_service,
+            HEARTBEAT,
+            Metrics::new(),
+            RATE,
+            throttle_tx,
+        );
+
+        wait_for_close(send_task, recv_task).await;
+    }
+
+    /// Serves a websocket and reports when it has been torn down
+    fn serve(shutdown: CancellationToken) -> (String, oneshot::Receiver<()>) {
+        serve_with(shutdown, broadcast::channel(16).0)
+    }
+
+    /// Serves a websocket that is sent what is broadcast on the channel
+    fn serve_with(
+        shutdown: CancellationToken,
+        tx: broadcast::Sender<GameBroadcast>,
+    ) -> (String, oneshot::Receiver<()>) {
+        let (closed_tx, closed_rx) = oneshot::channel();
+        let closed_tx = Arc::new(Mutex::new(Some(closed_tx)));
+
+        let app = Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| {
+                let closed_tx = closed_tx.clone();
+                let shutdown = shutdown.clone();
+                let tx = tx.clone();
+                async move {
+                    ws.on_upgrade(move |socket| async move {
+                        run_socket(socket, shutdown, tx).await;
+                        if let Some(closed_tx) = closed_tx.lock().unwrap().take() {
+                            let _ = closed_tx.send(());
+                        }
+                    })
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        (format!("ws://{}/ws", addr), closed_rx)
+    }
+
+    #[tokio::test]
+    async fn clients_are_pinged() {
+        let (url, _closed) = serve(CancellationToken::new());
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("No message received")
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(message, tungstenite::Message::Ping(_)));
+    }
+
+    #[tokio::test]
+    async fn clients_answering_pings_stay_connected() {
+        let (url, mut closed) = serve(CancellationToken::new());
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        // Reading lets the client answer the pings
+        let read = async { while client.next().await.is_some() {} };
+        let _ = tokio::time::timeout(HEARTBEAT.idle_timeout() * 3, read).await;
+
+        assert!(closed.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn silent_clients_are_disconnected() {
+        let (url, closed) = serve(CancellationToken::new());
+        let (_client, _) = connect_async(url).await.unwrap();
+
+        // The client never reads, so it never answers the pings
+        tokio::time::timeout(HEARTBEAT.idle_timeout() * 3, closed)
+            .await
+            .expect("Socket was not torn down")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_are_told_to_reconnect_on_shutdown() {
+        let shutdown = CancellationToken::new();
+        let (url, closed) = serve(shutdown.clone());
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        shutdown.cancel();
+        let messages: Vec<tungstenite::Message> = tokio::time::timeout(
+            Duration::from_secs(1),
+            client.by_ref().filter_map(|m| async { m.ok() }).collect(),
+        )
+        .await
+        .expect("Socket was not closed");
+
+        let reconnect = messages
+            .iter()
+            .find_map(|m| match m {
+                tungstenite::Message::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+            .expect("No reconnect message received");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&reconnect).unwrap()["type"],
+            "reconnect"
+        );
+        assert!(messages.iter().any(|m| matches!(
+            m,
+            tungstenite::Message::Close(Some(frame)) if u16::from(frame.code) == close_code::RESTART
+        )));
+        closed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn players_are_not_marked_inactive_when_shutting_down_within_the_grace_period() {
+        // The database is unreachable, so marking the player would fail
+        let game_repo = GameRepo::new(PgPool::connect_lazy("postgres://localhost/unused").unwrap());
+        let presence = PresenceTracker::new(Duration::from_secs(60), HEARTBEAT.interval);
+        let shutdown = CancellationToken::new();
+
+        let released = release_presence(
+            &game_repo,
+            &presence,
+            &shutdown,
+            Some(Uuid::from_u128(2)),
+            Uuid::nil(),
+        );
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            shutdown.cancel();
+        };
+        let (released, _) = tokio::time::timeout(Duration::from_secs(1), async {
+            tokio::join!(released, cancel)
+        })
+        .await
+        .expect("Grace period was not cut short");
+
+        assert!(!released.unwrap());
+    }
+
+    #[tokio::test]
+    async fn flooding_clients_are_slowed_down_then_disconnected() {
+        let (url, closed) = serve(CancellationToken::new());
+        let (mut client, _) = connect_async(url).await.unwrap();
+
+        // Messages that are not game actions never reach the database
+        for _ in 0..10 {
+            let _ = client
+                .send(tungstenite::Message::Text("flood".into()))
+                .await;
+        }
+        let messages: Vec<tungstenite::Message> = tokio::time::timeout(
+            Duration::from_secs(1),
+            client.by_ref().filter_map(|m| async { m.ok() }).collect(),
+        )
+        .await
+        .expect("Socket was not closed");
+
+        let texts: Vec<serde_json::Value> = messages
+            .iter()
+            .filter_map(|m| match m {
+                tungstenite::Message::Text(text) => serde_json::from_str(text).ok(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0]["type"], "rateLimited");
+        assert!(messages.iter().any(|m| matches!(
+            m,
+            tungstenite::Message::Close(Some(frame)) if u16::from(frame.code) == close_code::POLICY
+        )));
+        closed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn removed_members_are_disconnected() {
+        let (tx, _rx) = broadcast::channel(16);
+        let (url, closed) = serve_with(CancellationToken::new(), tx.clone());
+        let (mut client, _) = connect_async(url).await.unwrap();
+        while tx.receiver_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let removed = |user_id| GameBroadcast {
+            game_id: Uuid::nil(),
+            message: GameMessage::MemberRemoved { user_id },
+        };
+        tx.send(removed(Uuid::from_u128(2))).unwrap();
+        tx.send(removed(USER_ID)).unwrap();
+        let messages: Vec<tungstenite::Message> = tokio::time::timeout(
+            Duration::from_secs(1),
+            client.by_ref().filter_map(|m| async { m.ok() }).collect(),
+        )
+        .await
+        .expect("Socket was not closed");
+
+        let texts: Vec<serde_json::Value> = messages
+            .iter()
+            .filter_map(|m| match m {
+                tungstenite::Message::Text(text) => serde_json::from_str(text).ok(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0]["type"], "notification");
+        assert!(messages.iter().any(|m| matches!(
+            m,
+            tungstenite::Message::Close(Some(frame)) if u16::from(frame.code) == close_code::POLICY
+        )));
+        closed.await.unwrap();
+    }
 }
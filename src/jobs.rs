@@ -0,0 +1,377 @@
+use std::{future::Future, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, SubsecRound, TimeZone, Utc};
+use futures::future::BoxFuture;
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+use crate::{
+    error::{AppError, AppResult},
+    repositories::jobs::JobRepo,
+};
+
+/// When a background job runs
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// Runs when the scheduler starts and then every interval after a run
+    Interval(Duration),
+    /// Runs at the times matching a cron expression, in UTC
+    ///
+    /// The expression includes seconds, e.g. `0 0 3 * * *` runs daily at 3am.
+    Cron(Box<cron::Schedule>),
+    /// Never runs, for jobs that have been turned off
+    Never,
+}
+
+impl Schedule {
+    /// Runs every given number of seconds, or never when it is 0
+    pub fn every_seconds(seconds: u64) -> Self {
+        match seconds {
+            0 => Schedule::Never,
+            seconds => Schedule::Interval(Duration::from_secs(seconds)),
+        }
+    }
+
+    pub fn cron(expression: &str) -> AppResult<Self> {
+        cron::Schedule::from_str(expression)
+            .map(|s| Schedule::Cron(Box::new(s)))
+            .map_err(|e| {
+                AppError::ValidationError(format!("Invalid cron expression {}: {}", expression, e))
+            })
+    }
+
+    /// How long to wait before the first run, when the scheduler starts
+    pub fn initial_delay(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Interval(_) => Some(Duration::ZERO),
+            _ => self.next_delay(now),
+        }
+    }
+
+    /// How long to wait before the next run, `None` if the job should not run
+    /// again
+    pub fn next_delay(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Interval(interval) => Some(*interval),
+            Schedule::Cron(schedule) => schedule
+                .after(&now)
+                .next()
+                .map(|next| (next - now).to_std().unwrap_or(Duration::ZERO)),
+            Schedule::Never => None,
+        }
+    }
+
+    /// The occurrence a run scheduled for the given time belongs to
+    ///
+    /// Every instance works out the same occurrences, so that each is only
+    /// run once however many instances share the database. Intervals are
+    /// counted from the Unix epoch for this, and cron times are already
+    /// shared.
+    pub fn occurrence(&self, scheduled: DateTime<Utc>) -> DateTime<Utc> {
+        let scheduled = scheduled.trunc_subsecs(0);
+        match self {
+            Schedule::Interval(interval) => {
+                let interval = interval.as_secs().max(1) as i64;
+                let start = scheduled.timestamp() - scheduled.timestamp().rem_euclid(interval);
+                Utc.timestamp_opt(start, 0).unwrap()
+            }
+            _ => scheduled,
+        }
+    }
+}
+
+type JobFn = Arc<dyn Fn() -> BoxFuture<'static, AppResult<()>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Job {
+    name: String,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+/// Runs named background jobs on their schedules
+///
+/// Each run takes a Postgres advisory lock named after the job and claims the
+/// occurrence of the schedule in the job history, so when several instances
+/// of the app share a database only one of them runs each occurrence of a
+/// job. Every run is recorded in the job history.
+pub struct Scheduler {
+    job_repo: JobRepo,
+    jobs: Vec<Job>,
+}
+
+/// A handle on the running scheduler, used to shut it down
+pub struct SchedulerHandle {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn new(job_repo: JobRepo) -> Self {
+        Self {
+            job_repo,
+            jobs: vec![],
+        }
+    }
+
+    /// Adds a job, the name must be unique since it names the job's lock
+    pub fn add<F, Fut>(mut self, name: &str, schedule: Schedule, run: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AppResult<()>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name: name.to_owned(),
+            schedule,
+            run: Arc::new(move || Box::pin(run())),
+        });
+        self
+    }
+
+    /// Starts running every job in the background
+    pub fn start(self) -> SchedulerHandle {
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let tasks = self
+            .jobs
+            .into_iter()
+            .map(|job| {
+                tokio::spawn(run_on_schedule(
+                    job,
+                    self.job_repo.clone(),
+                    shutdown_rx.clone(),
+                ))
+            })
+            .collect();
+
+        SchedulerHandle { shutdown, tasks }
+    }
+}
+
+impl SchedulerHandle {
+    /// Stops scheduling new runs and waits for the jobs that are running to
+    /// finish
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                tracing::error!("Job panicked: {:?}", e);
+            }
+        }
+        tracing::info!("Scheduler stopped");
+    }
+}
+
+async fn run_on_schedule(job: Job, job_repo: JobRepo, mut shutdown: watch::Receiver<bool>) {
+    let mut now = Utc::now();
+    let mut delay = job.schedule.initial_delay(now);
+    if delay.is_none() {
+        tracing::info!("Job {} is disabled", job.name);
+    }
+
+    while let Some(next) = delay {
+        let scheduled = now + chrono::Duration::from_std(next).unwrap_or(chrono::Duration::zero());
+        tokio::select! {
+            _ = tokio::time::sleep(next) => (),
+            _ = shutdown.changed() => break,
+        }
+        if *shutdown.borrow() {
+            break;
+        }
+
+        let occurrence = job.schedule.occurrence(scheduled);
+        if let Err(e) = run_once(&job, &job_repo, occurrence).await {
+            tracing::error!("Could not run job {}: {:?}", job.name, e);
+        }
+        now = Utc::now();
+        delay = job.schedule.next_delay(now);
+    }
+}
+
+/// Runs the job once for the occurrence and records the run, unless another
+/// instance is running it already or has run the occurrence
+async fn run_once(job: &Job, job_repo: &JobRepo, occurrence: DateTime<Utc>) -> AppResult<()> {
+    let lock = match job_repo.try_lock(&job.name).await? {
+        Some(lock) => lock,
+        None => {
+            tracing::debug!("Job {} is running elsewhere, skipping", job.name);
+            return Ok(());
+        }
+    };
+
+    // The lock is released even when the run cannot be recorded, or every
+    // instance would skip the job from then on
+    let result = async {
+        let run_id = match job_repo
+            .start_run(&job.name, occurrence.naive_utc())
+            .await?
+        {
+            Some(run_id) => run_id,
+            None => {
+                tracing::debug!("Job {} already ran for {}, skipping", job.name, occurrence);
+                return Ok(());
+            }
+        };
+        tracing::debug!("Running job {}", job.name);
+        let started = Instant::now();
+        let error = (job.run)().await.err().map(|e| format!("{:?}", e));
+        match &error {
+            None => tracing::info!("Job {} finished in {:?}", job.name, started.elapsed()),
+            Some(e) => tracing::error!("Job {} failed: {}", job.name, e),
+        }
+        job_repo.finish_run(&run_id, error.as_deref()).await
+    }
+    .await;
+
+    let unlocked = lock.unlock().await;
+    result.and(unlocked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 19, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn intervals_run_at_start_and_then_every_interval() {
+        let schedule = Schedule::every_seconds(60);
+
+        assert_eq!(schedule.initial_delay(at(0, 0, 0)), Some(Duration::ZERO));
+        assert_eq!(
+            schedule.next_delay(at(0, 0, 0)),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn cron_schedules_wait_for_the_next_matching_time() {
+        let schedule = Schedule::cron("0 0 3 * * *").unwrap();
+
+        assert_eq!(
+            schedule.initial_delay(at(2, 30, 0)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            schedule.next_delay(at(3, 0, 0)),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn zero_intervals_never_run() {
+        let schedule = Schedule::every_seconds(0);
+
+        assert_eq!(schedule.initial_delay(at(0, 0, 0)), None);
+    }
+
+    #[test]
+    fn invalid_cron_expressions_are_rejected() {
+        assert!(Schedule::cron("every day").is_err());
+    }
+
+    /// A pool of its own, so that its connections never hold the locks taken
+    /// by another
+    async fn test_pool() -> PgPool {
+        dotenv::dotenv().ok();
+        let url = std::env::var("TEST_DATABASE_URL").expect("Did not find database URL");
+        let client = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!().run(&client).await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn locks_dropped_without_unlocking_are_released() {
+        let job_name = format!("test job {}", rand::random::<u64>());
+        let (ours, theirs) = (
+            JobRepo::new(test_pool().await),
+            JobRepo::new(test_pool().await),
+        );
+
+        let lock = ours.try_lock(&job_name).await.unwrap().unwrap();
+        assert!(theirs.try_lock(&job_name).await.unwrap().is_none());
+        drop(lock);
+
+        // The server releases the lock once it notices the connection closed
+        let mut released = None;
+        for _ in 0..50 {
+            released = theirs.try_lock(&job_name).await.unwrap();
+            if released.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        released
+            .expect("lock was not released")
+            .unlock()
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn occurrences_are_shared_between_instances() {
+        let schedule = Schedule::every_seconds(60);
+        assert_eq!(schedule.occurrence(at(1, 2, 3)), at(1, 2, 0));
+        assert_eq!(schedule.occurrence(at(1, 2, 59)), at(1, 2, 0));
+
+        let schedule = Schedule::cron("0 0 3 * * *").unwrap();
+        assert_eq!(schedule.occurrence(at(3, 0, 0)), at(3, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn each_occurrence_runs_once_across_instances() {
+        let job_name = format!("test job {}", rand::random::<u64>());
+        let pool = test_pool().await;
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let start = || {
+            let runs = runs.clone();
+            Scheduler::new(JobRepo::new(pool.clone()))
+                .add(
+                    &job_name,
+                    Schedule::Interval(Duration::from_millis(200)),
+                    move || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        async { Ok(()) }
+                    },
+                )
+                .start()
+        };
+        let (ours, theirs) = (start(), start());
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        ours.shutdown().await;
+        theirs.shutdown().await;
+
+        let (recorded, occurrences): (i64, i64) = sqlx::query_as(
+            "SELECT count(*), count(DISTINCT scheduled_for) FROM job_runs WHERE job_name = $1",
+        )
+        .bind(&job_name)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        // Intervals shorter than a second share the occurrence of the second
+        // they are in
+        assert!((2..=4).contains(&recorded), "{} runs recorded", recorded);
+        assert_eq!(recorded, occurrences);
+        assert_eq!(runs.load(Ordering::SeqCst) as i64, recorded);
+    }
+
+    #[tokio::test]
+    async fn shutdown_does_not_wait_for_the_next_run() {
+        let job_repo = JobRepo::new(PgPool::connect_lazy("postgres://localhost/unused").unwrap());
+        let handle = Scheduler::new(job_repo)
+            .add("yearly", Schedule::cron("0 0 0 1 1 *").unwrap(), || async {
+                Ok(())
+            })
+            .start();
+
+        tokio::time::timeout(Duration::from_secs(1), handle.shutdown())
+            .await
+            .expect("scheduler did not stop");
+    }
+}
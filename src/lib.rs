@@ -10,16 +10,38 @@ use repositories::games::GameRepo;
 use rust_embed::RustEmbed;
 use tokio::sync::broadcast;
 
-use crate::repositories::users::UserRepo;
-use crate::services::session::SessionManager;
+pub use crate::config::AppConfig;
+use crate::handlers::{
+    health::MetricsToken,
+    websocket::{Heartbeat, SocketShutdown},
+};
+use crate::metrics::Metrics;
+use crate::repositories::{
+    audit::AuditRepo, health::HealthRepo, identities::IdentityRepo, users::UserRepo,
+};
+use crate::services::{
+    accounts::AccountEmails,
+    auth::Lockout,
+    oidc::OidcLogin,
+    presence::PresenceTracker,
+    rate_limit::{AuthRateLimiter, Rate},
+    session::SessionManager,
+};
 
+pub mod cli;
+pub mod config;
 pub mod error;
 pub mod extractors;
 pub mod handlers;
+pub mod jobs;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod redact;
 pub mod repositories;
 pub mod services;
 pub mod session;
+pub mod telemetry;
 pub mod view;
 
 #[derive(Clone, Debug)]
@@ -29,13 +51,24 @@ pub struct AppState {
     pub user_repo: UserRepo,
     pub game_repo: GameRepo,
     pub session_manager: SessionManager,
-}
-
-#[derive(Clone, Debug)]
-pub struct AppConfig {
-    pub database_url: String,
-    pub session_secret: String,
-    pub app_log: String,
+    pub presence: PresenceTracker,
+    pub heartbeat: Heartbeat,
+    pub shutdown: SocketShutdown,
+    pub health_repo: HealthRepo,
+    pub metrics: Metrics,
+    /// Required to read the metrics, which are disabled without it
+    pub metrics_token: Option<MetricsToken>,
+    pub auth_limits: AuthRateLimiter,
+    pub audit_repo: AuditRepo,
+    pub lockout: Lockout,
+    pub account_emails: AccountEmails,
+    pub identity_repo: IdentityRepo,
+    /// Single sign-on with an OpenID Connect provider, when configured
+    pub oidc: Option<OidcLogin>,
+    /// How fast each websocket may send game messages
+    pub socket_rate: Rate,
+    /// Whether the address of callers is taken from `X-Forwarded-For`
+    pub trust_forwarded_for: bool,
 }
 
 #[derive(RustEmbed)]
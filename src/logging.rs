@@ -0,0 +1,118 @@
+use axum::http::Request;
+use tracing::{Span, Subscriber};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::{
+    config::{AppConfig, LogFormat},
+    telemetry::{set_remote_parent, Telemetry},
+};
+
+/// The header carrying the id of each request, set by the server unless the
+/// client or a proxy already did
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Sets up logging to stdout, in the configured format, and the export of
+/// traces when there is a collector to send them to
+pub fn init(app_config: &AppConfig, telemetry: Option<&Telemetry>) {
+    let log_layer = match app_config.log_format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => json_layer(std::io::stdout).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(log_layer.with_filter(EnvFilter::new(&app_config.app_log)))
+        .with(telemetry.map(|t| t.layer()))
+        .init();
+}
+
+/// Writes every event as a JSON object, along with the fields of the spans it
+/// happened in such as the request id and game id
+fn json_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer().json().with_writer(writer)
+}
+
+/// The span for an HTTP request, tagged with its request id
+///
+/// Only the path is recorded, since query strings may carry tokens. When the
+/// caller is tracing the request the span joins its trace.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id,
+    );
+    set_remote_parent(&span, request.headers());
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::users::User;
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_logs_carry_the_span_fields_without_secrets() {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::registry().with(json_layer(move || writer.clone()));
+        let request = Request::get("/api/login?token=secret")
+            .header(REQUEST_ID_HEADER, "abc123")
+            .body(())
+            .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let user = User {
+                id: Uuid::nil(),
+                email: "host@example.com".into(),
+                password: "$argon2id$v=19$secret".into(),
+            };
+            request_span(&request).in_scope(|| tracing::error!(?user, "Could not log in"));
+        });
+
+        let logged = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(logged.trim()).unwrap();
+        assert_eq!(line["span"]["request_id"], "abc123");
+        assert_eq!(line["span"]["path"], "/api/login");
+        assert!(!logged.contains("argon2"));
+        assert!(!logged.contains("token=secret"));
+    }
+}
@@ -1,90 +1,452 @@
 use std::{
     collections::HashSet,
-    net::{Ipv4Addr, SocketAddr},
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
+use axum::{http::HeaderName, middleware};
 use axum_sessions::SessionLayer;
+use clap::Parser;
 use dotenv::dotenv;
-use tokio::sync::broadcast;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, oneshot},
+};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 use namethat::{
+    cli::{self, Cli, Command},
+    config::{ConfigError, MailerKind},
     error::AppResult,
-    handlers::AppRouter,
-    repositories::{games::GameRepo, users::UserRepo},
-    services::session::SessionManager,
+    handlers::{
+        health::MetricsToken,
+        websocket::{Heartbeat, SocketShutdown},
+        AppRouter,
+    },
+    jobs::{Schedule, Scheduler},
+    logging::{self, request_span, REQUEST_ID_HEADER},
+    metrics::{track_requests, Metrics},
+    repositories::{
+        audit::AuditRepo, games::GameRepo, health::HealthRepo, identities::IdentityRepo,
+        jobs::JobRepo, users::UserRepo,
+    },
+    services::{
+        accounts::AccountEmails,
+        auth::Lockout,
+        game::{reveal_next_answer, GameBroadcastService},
+        mailer::{FileMailer, LogMailer, Mailer, SmtpMailer},
+        oidc::{OidcClient, OidcLogin, OidcSettings},
+        presence::PresenceTracker,
+        rate_limit::{AuthRateLimiter, Rate},
+        session::SessionManager,
+        tokens::TokenSigner,
+    },
     session::SessionStore,
+    telemetry::Telemetry,
     AppConfig, AppState,
 };
 
+const DEFAULT_CONFIG_FILE: &str = "namethat.toml";
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let session_secret = std::env::var("SESSION_SECRET").expect("SESSION_SECRET must be set");
-    let app_log = std::env::var("APP_LOG").unwrap_or("namethat=debug".to_string());
-    let app_config = AppConfig {
-        database_url,
-        session_secret,
-        app_log,
+    let cli = Cli::parse();
+    let app_config = match load_config(cli.config) {
+        Ok(app_config) => app_config,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(1);
+        }
     };
-
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(&app_config.app_log))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => (),
-        _ = serve(&app_config) => (),
-        _ = tasks(&app_config) => (),
+    if cli.print_config {
+        print!("{}", app_config.to_redacted_toml());
+        return;
     }
 
-    tracing::info!("Shutting down");
-}
+    let telemetry = match &app_config.otlp_endpoint {
+        Some(endpoint) => match Telemetry::new(endpoint) {
+            Ok(telemetry) => Some(telemetry),
+            Err(e) => {
+                eprintln!("Could not set up trace export: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    logging::init(&app_config, telemetry.as_ref());
 
-async fn serve(app_config: &AppConfig) -> AppResult<()> {
-    let client = sqlx::PgPool::connect(&app_config.database_url)
+    let client = PgPoolOptions::new()
+        .max_connections(app_config.database_max_connections)
+        .min_connections(app_config.database_min_connections)
+        .connect(&app_config.database_url)
         .await
         .expect("Could not connect to database");
 
+    let result = match cli.command {
+        None | Some(Command::Serve) => {
+            serve_app(app_config, client).await;
+            Ok(())
+        }
+        Some(command) => cli::run(command, client, &mut std::io::stdout()).await,
+    };
+
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
+    }
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Serves the app until it is asked to stop, then shuts down gracefully
+async fn serve_app(app_config: AppConfig, client: PgPool) {
     sqlx::migrate!().run(&client).await.unwrap();
 
     let session_store = SessionStore::from_client(client.clone());
-    let user_repo = UserRepo::new(client.clone());
-    let game_repo = GameRepo::new(client.clone());
-    let user_set = Arc::new(Mutex::new(HashSet::new()));
-    let session_manager = SessionManager::new(session_store.clone());
-    let (tx, _) = broadcast::channel(50);
+    let metrics = Metrics::new();
+    metrics.set_pool_max_connections(app_config.database_max_connections);
+    let (tx, _) = broadcast::channel(app_config.broadcast_capacity);
+    let mailer: Arc<dyn Mailer> = match app_config.mailer {
+        MailerKind::Log => Arc::new(LogMailer),
+        MailerKind::File => Arc::new(FileMailer::new(&app_config.mail_dir)),
+        MailerKind::Smtp => {
+            let url = app_config.smtp_url.as_deref().unwrap_or_default();
+            Arc::new(SmtpMailer::new(url, &app_config.mail_from).expect("Could not set up SMTP"))
+        }
+    };
+    let oidc = app_config.oidc_issuer.as_ref().map(|issuer| OidcLogin {
+        client: OidcClient::new(OidcSettings {
+            issuer: issuer.clone(),
+            client_id: app_config.oidc_client_id.clone().unwrap_or_default(),
+            client_secret: app_config.oidc_client_secret.clone(),
+            redirect_url: format!(
+                "{}/auth/oidc/callback",
+                app_config.public_url.trim_end_matches('/')
+            ),
+            scopes: app_config.oidc_scopes.clone(),
+        })
+        .expect("Could not set up OpenID Connect"),
+        provider_name: app_config.oidc_provider_name.clone(),
+        create_accounts: app_config.oidc_create_accounts,
+        link_by_email: app_config.oidc_link_by_email,
+    });
+    let state = Arc::new(AppState {
+        user_set: Arc::new(Mutex::new(HashSet::new())),
+        tx,
+        user_repo: UserRepo::new(client.clone()),
+        game_repo: GameRepo::new(client.clone()),
+        session_manager: SessionManager::new(session_store.clone()),
+        presence: PresenceTracker::new(
+            Duration::from_secs(app_config.reconnect_grace_seconds),
+            Duration::from_secs(app_config.inactive_sweep_seconds),
+        ),
+        heartbeat: Heartbeat {
+            interval: Duration::from_secs(app_config.heartbeat_interval_seconds),
+            timeout: Duration::from_secs(app_config.heartbeat_timeout_seconds),
+        },
+        shutdown: SocketShutdown::default(),
+        health_repo: HealthRepo::new(client.clone()),
+        metrics,
+        metrics_token: app_config.metrics_token.as_deref().map(MetricsToken::new),
+        auth_limits: AuthRateLimiter::new(
+            Rate::per_minute(app_config.auth_ip_burst, app_config.auth_ip_per_minute),
+            Rate::per_minute(
+                app_config.auth_email_burst,
+                app_config.auth_email_per_minute,
+            ),
+        ),
+        audit_repo: AuditRepo::new(client.clone()),
+        lockout: Lockout {
+            max_failures: app_config.login_max_failures,
+            base: Duration::from_secs(app_config.login_lockout_seconds),
+            max: Duration::from_secs(app_config.login_lockout_max_seconds),
+        },
+        account_emails: AccountEmails::new(
+            mailer,
+            TokenSigner::new(app_config.session_secret.as_bytes()),
+            &app_config.public_url,
+            Duration::from_secs(app_config.email_verification_ttl_seconds),
+            Duration::from_secs(app_config.password_reset_ttl_seconds),
+        ),
+        identity_repo: IdentityRepo::new(client.clone()),
+        oidc,
+        socket_rate: Rate::per_second(
+            app_config.socket_message_burst,
+            app_config.socket_messages_per_second,
+        ),
+        trust_forwarded_for: app_config.trust_forwarded_for,
+    });
+
+    let scheduler = jobs(
+        &app_config,
+        JobRepo::new(client.clone()),
+        session_store.clone(),
+        state.clone(),
+    )
+    .start();
+    let heartbeat = tokio::spawn(record_presence(state.clone()));
+
+    let (drain_tx, drain_rx) = oneshot::channel::<()>();
+    let mut server = Box::pin(serve(&app_config, session_store, state.clone(), async {
+        let _ = drain_rx.await;
+    }));
+    let stopped = tokio::select! {
+        _ = shutdown_signal() => false,
+        result = &mut server => {
+            if let Err(e) = result {
+                tracing::error!("Server stopped: {:?}", e);
+            }
+            true
+        }
+    };
+
+    // Stop accepting connections, then give the requests in flight, the game
+    // sockets and the running jobs a chance to finish
+    tracing::info!("Shutting down");
+    let _ = drain_tx.send(());
+    let drain = async {
+        tokio::join!(
+            async {
+                if !stopped {
+                    if let Err(e) = server.await {
+                        tracing::error!("Error draining requests: {:?}", e);
+                    }
+                }
+            },
+            state.shutdown.close_all(),
+            scheduler.shutdown(),
+        )
+    };
+    let timeout = Duration::from_secs(app_config.shutdown_timeout_seconds);
+    if tokio::time::timeout(timeout, drain).await.is_err() {
+        tracing::warn!("Gave up draining connections after {:?}", timeout);
+    }
+    heartbeat.abort();
+}
+
+/// Waits for the process to be asked to stop, with SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+/// Serves the app until the shutdown future completes, then waits for the
+/// requests in flight to finish
+async fn serve(
+    app_config: &AppConfig,
+    session_store: SessionStore,
+    state: Arc<AppState>,
+    shutdown: impl Future<Output = ()>,
+) -> AppResult<()> {
+    let mut session_layer = SessionLayer::new(session_store, app_config.session_secret.as_bytes())
+        .with_cookie_name(&app_config.session_cookie_name)
+        .with_secure(app_config.session_cookie_secure)
+        .with_same_site_policy(app_config.session_cookie_same_site.into())
+        .with_session_ttl(Some(Duration::from_secs(app_config.session_ttl_seconds)));
+    if let Some(domain) = &app_config.session_cookie_domain {
+        session_layer = session_layer.with_cookie_domain(domain);
+    }
 
     let app = AppRouter::build()
-        .layer(SessionLayer::new(
-            session_store,
-            app_config.session_secret.as_bytes(),
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_requests,
         ))
-        .layer(TraceLayer::new_for_http())
-        .with_state(Arc::new(AppState {
-            user_set,
-            tx,
-            user_repo,
-            game_repo,
-            session_manager,
-        }));
-
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 3000));
+        .layer(session_layer)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                    MakeRequestUuid,
+                ))
+                .layer(TraceLayer::new_for_http().make_span_with(request_span))
+                .layer(PropagateRequestIdLayer::new(HeaderName::from_static(
+                    REQUEST_ID_HEADER,
+                ))),
+        )
+        .with_state(state);
+
+    let addr = app_config.bind_address;
     tracing::debug!("listening on {}", addr);
 
     Ok(axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
         .await?)
 }
 
-async fn tasks(_app_config: &AppConfig) -> AppResult<()> {
+/// Loads the given config file, or `namethat.toml` when there is one, with the
+/// environment overriding it
+fn load_config(file: Option<PathBuf>) -> Result<AppConfig, ConfigError> {
+    let file = file.or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.exists()));
+
+    AppConfig::load(file.as_deref())
+}
+
+/// Schedules the maintenance jobs
+fn jobs(
+    app_config: &AppConfig,
+    job_repo: JobRepo,
+    session_store: SessionStore,
+    state: Arc<AppState>,
+) -> Scheduler {
+    let idle_period = Duration::from_secs(app_config.idle_game_hours * 60 * 60);
+    let retention_period =
+        Duration::from_secs(app_config.finished_game_retention_days * 24 * 60 * 60);
+    let history_period = Duration::from_secs(app_config.job_history_retention_days * 24 * 60 * 60);
+
+    Scheduler::new(job_repo.clone())
+        .add(
+            "reveal answers",
+            Schedule::every_seconds(app_config.reveal_check_seconds),
+            {
+                let state = state.clone();
+                move || reveal_answers(state.clone())
+            },
+        )
+        .add(
+            "sweep inactive players",
+            Schedule::every_seconds(app_config.inactive_sweep_seconds),
+            {
+                let state = state.clone();
+                move || sweep_inactive_players(state.clone())
+            },
+        )
+        .add(
+            "finish idle games",
+            Schedule::every_seconds(app_config.idle_game_sweep_seconds),
+            {
+                let state = state.clone();
+                move || finish_idle_games(state.clone(), idle_period)
+            },
+        )
+        .add(
+            "clean up sessions",
+            Schedule::every_seconds(app_config.session_cleanup_seconds),
+            move || {
+                let session_store = session_store.clone();
+                async move { Ok(session_store.cleanup().await?) }
+            },
+        )
+        .add(
+            "delete finished games",
+            Schedule::every_seconds(app_config.finished_game_sweep_seconds),
+            move || delete_finished_games(state.clone(), retention_period),
+        )
+        .add(
+            "prune job history",
+            Schedule::cron("0 0 3 * * *").expect("job history schedule is valid"),
+            move || {
+                let job_repo = job_repo.clone();
+                async move {
+                    let deleted = job_repo.delete_runs(history_period).await?;
+                    tracing::info!("Deleted {} job runs", deleted);
+                    Ok(())
+                }
+            },
+        )
+}
+
+/// Records the players and hosts connected to this server every heartbeat, for
+/// as long as it runs
+///
+/// Every instance records its own, so this is not a job, which would only run
+/// on one of them at a time.
+async fn record_presence(state: Arc<AppState>) {
+    let mut heartbeat = tokio::time::interval(state.presence.heartbeat_interval());
     loop {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-        // tracing::info!("running tasks");
+        heartbeat.tick().await;
+        if let Err(e) = state
+            .game_repo
+            .record_presence(
+                &state.presence.connected(),
+                &state.presence.connected_hosts(),
+            )
+            .await
+        {
+            tracing::error!("Could not record presence: {:?}", e);
+        }
+    }
+}
+
+/// Reveals the next answer of every round being revealed automatically that
+/// is due one
+async fn reveal_answers(state: Arc<AppState>) -> AppResult<()> {
+    for (round_id, game_id) in state.game_repo.take_due_reveals().await? {
+        let broadcast_service =
+            GameBroadcastService::new(game_id, state.game_repo.clone(), state.tx.clone());
+        reveal_next_answer(&state.game_repo, &broadcast_service, &round_id).await?;
     }
+
+    Ok(())
+}
+
+/// Marks players and hosts inactive that were left active without a
+/// connection on any server, such as after a server was restarted while they
+/// were playing.
+async fn sweep_inactive_players(state: Arc<AppState>) -> AppResult<()> {
+    let presence = &state.presence;
+    let mut game_ids = state
+        .game_repo
+        .mark_disconnected_players_inactive(presence.grace_period(), presence.timeout())
+        .await?;
+    game_ids.extend(
+        state
+            .game_repo
+            .mark_disconnected_hosts_inactive(presence.grace_period(), presence.timeout())
+            .await?,
+    );
+    game_ids.sort();
+    game_ids.dedup();
+
+    for game_id in game_ids {
+        tracing::info!("Marked disconnected players inactive in game {}", game_id);
+        GameBroadcastService::new(game_id, state.game_repo.clone(), state.tx.clone())
+            .broadcast_game_state()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Finishes the games that nobody has played within the idle period, so that
+/// abandoned games do not stay open forever.
+async fn finish_idle_games(state: Arc<AppState>, idle_period: Duration) -> AppResult<()> {
+    let game_ids = state.game_repo.find_idle_games(idle_period).await?;
+
+    for game_id in game_ids.iter() {
+        state.game_repo.end(game_id).await?;
+        GameBroadcastService::new(*game_id, state.game_repo.clone(), state.tx.clone())
+            .broadcast_game_state()
+            .await?;
+    }
+    tracing::info!("Finished {} idle games", game_ids.len());
+
+    Ok(())
+}
+
+/// Deletes the games that finished longer ago than the retention period
+async fn delete_finished_games(state: Arc<AppState>, retention_period: Duration) -> AppResult<()> {
+    let deleted = state
+        .game_repo
+        .delete_finished_games(retention_period)
+        .await?;
+    tracing::info!("Deleted {} finished games", deleted);
+
+    Ok(())
 }
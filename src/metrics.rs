@@ -0,0 +1,272 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    core::Collector, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use uuid::Uuid;
+
+use crate::{error::AppResult, repositories::health::PoolUsage, AppState};
+
+/// The Prometheus metrics of the app, served from `/metrics`
+///
+/// Metrics are kept in a registry of their own rather than the global one, so
+/// that tests do not share them.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    websockets: IntGaugeVec,
+    broadcast_lags: IntCounter,
+    broadcast_skipped: IntCounter,
+    socket_messages_dropped: IntCounter,
+    game_actions: IntCounterVec,
+    game_action_errors: IntCounterVec,
+    http_requests: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("namethat".into()), None).expect("metrics prefix is valid");
+        let metrics = Self {
+            websockets: IntGaugeVec::new(
+                Opts::new("websockets_active", "Open websockets per game"),
+                &["game_id"],
+            )
+            .unwrap(),
+            broadcast_lags: IntCounter::new(
+                "broadcast_lag_events_total",
+                "Times a websocket fell too far behind the game broadcasts",
+            )
+            .unwrap(),
+            broadcast_skipped: IntCounter::new(
+                "broadcast_skipped_messages_total",
+                "Broadcasts dropped for websockets that fell behind",
+            )
+            .unwrap(),
+            socket_messages_dropped: IntCounter::new(
+                "websocket_messages_dropped_total",
+                "Game messages dropped from websockets sending too fast",
+            )
+            .unwrap(),
+            game_actions: IntCounterVec::new(
+                Opts::new("game_actions_total", "Game actions received"),
+                &["action"],
+            )
+            .unwrap(),
+            game_action_errors: IntCounterVec::new(
+                Opts::new("game_action_errors_total", "Game actions that failed"),
+                &["action"],
+            )
+            .unwrap(),
+            http_requests: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time taken to answer HTTP requests",
+                ),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Database connections by state"),
+                &["state"],
+            )
+            .unwrap(),
+            pool_max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Most connections the database pool opens",
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 9] = [
+            Box::new(metrics.websockets.clone()),
+            Box::new(metrics.broadcast_lags.clone()),
+            Box::new(metrics.broadcast_skipped.clone()),
+            Box::new(metrics.socket_messages_dropped.clone()),
+            Box::new(metrics.game_actions.clone()),
+            Box::new(metrics.game_action_errors.clone()),
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.pool_connections.clone()),
+            Box::new(metrics.pool_max_connections.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metrics are only registered once");
+        }
+
+        metrics
+    }
+
+    /// Counts the socket as open until the returned guard is dropped
+    pub fn socket_opened(&self, game_id: &Uuid) -> OpenSocket {
+        self.websockets
+            .with_label_values(&[&game_id.to_string()])
+            .inc();
+        OpenSocket {
+            metrics: self.clone(),
+            game_id: *game_id,
+        }
+    }
+
+    /// Counts the socket as closed, dropping the game from the metrics once
+    /// it has no sockets left so that finished games do not pile up
+    fn socket_closed(&self, game_id: &Uuid) {
+        let game_id = game_id.to_string();
+        let sockets = self.websockets.with_label_values(&[&game_id]);
+        sockets.dec();
+        if sockets.get() <= 0 {
+            let _ = self.websockets.remove_label_values(&[&game_id]);
+        }
+    }
+
+    /// Counts a socket falling behind the broadcasts, and the broadcasts it
+    /// missed because of it
+    pub fn broadcast_lagged(&self, skipped: u64) {
+        self.broadcast_lags.inc();
+        self.broadcast_skipped.inc_by(skipped);
+    }
+
+    /// Counts a message dropped from a socket sending too fast
+    pub fn socket_message_dropped(&self) {
+        self.socket_messages_dropped.inc();
+    }
+
+    pub fn game_action(&self, action: &str, result: &AppResult<()>) {
+        self.game_actions.with_label_values(&[action]).inc();
+        if result.is_err() {
+            self.game_action_errors.with_label_values(&[action]).inc();
+        }
+    }
+
+    /// Records the size of the database pool, which it does not report itself
+    pub fn set_pool_max_connections(&self, max: u32) {
+        self.pool_max_connections.set(max as i64);
+    }
+
+    /// Renders the metrics in the Prometheus text format
+    pub fn render(&self, pool: PoolUsage) -> String {
+        self.pool_connections
+            .with_label_values(&["idle"])
+            .set(pool.idle as i64);
+        self.pool_connections
+            .with_label_values(&["in_use"])
+            .set(pool.size as i64 - pool.idle as i64);
+
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("metrics encode as text")
+    }
+}
+
+/// An open websocket, counted in the metrics until it is dropped
+pub struct OpenSocket {
+    metrics: Metrics,
+    game_id: Uuid,
+}
+
+impl Drop for OpenSocket {
+    fn drop(&mut self) {
+        self.metrics.socket_closed(&self.game_id);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+/// Records how long each request took, by route rather than path so that ids
+/// in the path do not create a series per game
+pub async fn track_requests<B>(
+    State(state): State<Arc<AppState>>,
+    path: MatchedPath,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    state
+        .metrics
+        .http_requests
+        .with_label_values(&[&method, path.as_str(), response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AppError;
+
+    use super::*;
+
+    fn pool() -> PoolUsage {
+        PoolUsage { size: 3, idle: 1 }
+    }
+
+    #[test]
+    fn games_without_sockets_are_dropped() {
+        let metrics = Metrics::new();
+        let game_id = Uuid::from_u128(1);
+
+        let first = metrics.socket_opened(&game_id);
+        let second = metrics.socket_opened(&game_id);
+        drop(first);
+        assert!(metrics.render(pool()).contains(
+            r#"namethat_websockets_active{game_id="00000000-0000-0000-0000-000000000001"} 1"#
+        ));
+
+        drop(second);
+        assert!(!metrics
+            .render(pool())
+            .contains("namethat_websockets_active{"));
+    }
+
+    #[test]
+    fn failed_actions_are_counted_as_errors() {
+        let metrics = Metrics::new();
+
+        metrics.game_action("endGame", &Ok(()));
+        metrics.game_action(
+            "endGame",
+            &Err(AppError::AuthorizationError("Not allowed".into())),
+        );
+
+        let rendered = metrics.render(pool());
+        assert!(rendered.contains(r#"namethat_game_actions_total{action="endGame"} 2"#));
+        assert!(rendered.contains(r#"namethat_game_action_errors_total{action="endGame"} 1"#));
+    }
+
+    #[test]
+    fn pool_usage_is_split_by_state() {
+        let metrics = Metrics::new();
+        metrics.set_pool_max_connections(10);
+
+        let rendered = metrics.render(pool());
+
+        assert!(rendered.contains(r#"namethat_db_pool_connections{state="idle"} 1"#));
+        assert!(rendered.contains(r#"namethat_db_pool_connections{state="in_use"} 2"#));
+        assert!(rendered.contains("namethat_db_pool_max_connections 10"));
+    }
+}
@@ -1,2 +1,5 @@
+pub mod audit;
 pub mod games;
+pub mod identities;
+pub mod jobs;
 pub mod users;
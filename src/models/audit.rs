@@ -0,0 +1,59 @@
+use std::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "auth_event_kind", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum AuthEventKind {
+    Registered,
+    LoginSucceeded,
+    /// The email matched no account or the password was wrong
+    LoginFailed,
+    /// The account was locked, so the password was not checked
+    LoginRefusedLocked,
+    AccountLocked,
+    RateLimited,
+    LoggedOut,
+    /// The password was right but the email has not been verified yet
+    LoginRefusedUnverified,
+    EmailVerified,
+    PasswordResetRequested,
+    PasswordReset,
+    /// An identity at an OpenID Connect provider was linked to the account
+    IdentityLinked,
+    /// The provider vouched for an identity that is linked to no account
+    LoginRefusedNoAccount,
+    /// Someone tried to register an email that already has an account, whose
+    /// owner was emailed about it
+    RegisterRefusedExisting,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewAuthEvent {
+    pub user_id: Option<Uuid>,
+    pub email: String,
+    pub kind: AuthEventKind,
+    pub ip: Option<IpAddr>,
+}
+
+/// An entry of the authentication audit log
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthEvent {
+    pub id: Uuid,
+    /// The account, unset when the email matched none
+    pub user_id: Option<Uuid>,
+    pub email: String,
+    pub kind: AuthEventKind,
+    pub ip: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AuthEventFilter {
+    pub email: Option<String>,
+    pub user_id: Option<Uuid>,
+}
@@ -12,6 +12,63 @@ pub enum GameStatus {
     Finished,
 }
 
+/// The kind of answer players are asked for in a round
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "game_mode", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum GameMode {
+    /// Players caption the image
+    Caption,
+    /// Players guess the title of the image
+    GuessTitle,
+    /// Players fill in the blank in the prompt
+    ///
+    /// The prompt must contain the [`GameMode::BLANK`] marker.
+    FillInTheBlank,
+    /// Players guess the expected answer for the image
+    ///
+    /// Answers are scored automatically against the game's answer keys, so the
+    /// game master does not need to judge them.
+    GuessTheAnswer,
+}
+
+/// How players that join after the game has started take part
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "late_join", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum LateJoin {
+    /// Late joiners observe the rest of the game
+    Observer,
+    /// Late joiners play from the next round with a score of zero
+    NextRound,
+    /// Late joiners play straight away, starting with the lowest score of the
+    /// other players
+    CatchUp,
+}
+
+/// The role of a user in a game
+///
+/// What each role may do is decided in one place, see
+/// [`crate::services::permissions`].
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "game_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum GameRole {
+    /// The user that created the game
+    Owner,
+    /// A registered user the owner has invited to run the game with them
+    CoHost,
+    /// A registered user the owner has invited to keep the answers clean
+    Moderator,
+    Player,
+    Observer,
+}
+
+impl GameMode {
+    /// The marker for the blank in a fill in the blank prompt
+    pub const BLANK: &'static str = "___";
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Game {
@@ -22,12 +79,41 @@ pub struct Game {
     /// This is the user that created the game and has permissions to run the
     /// game.
     pub user_id: Uuid,
+    /// The registered users invited to help run the game
+    pub members: Vec<GameMember>,
+    /// Whether the owner or a co-host is connected to the game
+    pub host_active: bool,
+    /// Whether the game has been paused by the host
+    pub paused: bool,
     /// The name of the game
     pub name: String,
     /// The images used for the game
     ///
     /// This is a list of URLs to images.
     pub image_urls: Vec<String>,
+    /// The prompts used for the game
+    ///
+    /// This is matched to `image_urls` by index. An empty or missing prompt
+    /// means the round only has an image.
+    pub prompts: Vec<String>,
+    /// The mode used for the rounds of the game
+    pub mode: GameMode,
+    /// The number of answers each player may give per round
+    pub max_answers_per_round: i32,
+    /// Whether the answers are revealed automatically once they are closed
+    pub auto_reveal: bool,
+    /// The number of seconds between answers when revealing automatically
+    pub reveal_interval_seconds: i32,
+    /// The number of typos allowed when matching answers against answer keys
+    pub answer_tolerance: i32,
+    /// The expected answers used to score guess the answer rounds
+    pub answer_keys: Vec<AnswerKey>,
+    /// How players that join after the game has started take part
+    ///
+    /// This also applies to observers that are promoted to players.
+    pub late_join: LateJoin,
+    /// Ready players needed before the game can start without forcing it
+    pub min_players: i32,
     /// The rounds for the game
     ///
     /// This list should only be created once the game has started, otherwise it
@@ -43,6 +129,27 @@ pub struct Game {
     pub winner: Option<Uuid>,
 }
 
+/// The expected answer for one of the images of a game
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnswerKey {
+    /// The index of the image in `image_urls`
+    pub image_index: i32,
+    pub expected_answer: String,
+    /// Other answers that are accepted as correct
+    pub aliases: Vec<String>,
+}
+
+/// A registered user invited to help run a game
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameMember {
+    pub user_id: Uuid,
+    pub email: String,
+    /// Either [`GameRole::CoHost`] or [`GameRole::Moderator`]
+    pub role: GameRole,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Player {
@@ -50,8 +157,88 @@ pub struct Player {
     pub game_id: Uuid,
     pub username: String,
     pub active: bool,
+    /// Whether the player only observes the game
+    ///
+    /// Observers may be promoted to players by the game master during the
+    /// game.
     pub is_observer: bool,
     pub score: i32,
+    /// The first round the player may answer in
+    ///
+    /// This is set for players that joined or were promoted during the game
+    /// and should sit out the round in progress.
+    pub first_round: Option<i32>,
+    /// Whether the observer has asked to be promoted to a player
+    pub promotion_requested: bool,
+    /// Whether the player is ready for the game to start
+    pub ready: bool,
+}
+
+impl Game {
+    /// Gets the role of a registered user, if they own or were invited to the
+    /// game
+    pub fn user_role(&self, user_id: &Uuid) -> Option<GameRole> {
+        if self.user_id == *user_id {
+            return Some(GameRole::Owner);
+        }
+
+        self.members
+            .iter()
+            .find(|m| m.user_id == *user_id)
+            .map(|m| m.role)
+    }
+
+    /// Checks whether the user may run the game, as its owner or a co-host
+    pub fn is_host(&self, user_id: &Uuid) -> bool {
+        matches!(
+            self.user_role(user_id),
+            Some(GameRole::Owner | GameRole::CoHost)
+        )
+    }
+
+    /// Gets the answer key for the image at the given index
+    pub fn answer_key(&self, image_index: i32) -> Option<&AnswerKey> {
+        self.answer_keys
+            .iter()
+            .find(|k| k.image_index == image_index)
+    }
+
+    /// The score a player joining late starts with when catching up
+    ///
+    /// This is the lowest score of the current players, so that late joiners
+    /// are not too far behind without overtaking anyone.
+    pub fn catch_up_score(&self) -> i32 {
+        self.players
+            .iter()
+            .filter(|p| !p.is_observer)
+            .map(|p| p.score)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The number of connected players, not observers, that are ready for the
+    /// game to start
+    pub fn ready_player_count(&self) -> i32 {
+        self.players
+            .iter()
+            .filter(|p| p.active && p.ready && !p.is_observer)
+            .count() as i32
+    }
+
+    /// The first round a player joining now may answer in, and the score they
+    /// start with.
+    ///
+    /// Players joining before the game has started play every round.
+    pub fn late_join_terms(&self) -> (Option<i32>, i32) {
+        if self.status == GameStatus::Pending {
+            return (None, 0);
+        }
+
+        match self.late_join {
+            LateJoin::CatchUp => (None, self.catch_up_score()),
+            LateJoin::Observer | LateJoin::NextRound => (Some(self.rounds.len() as i32 + 1), 0),
+        }
+    }
 }
 
 impl Player {
@@ -81,6 +268,10 @@ pub struct Round {
     /// This is duplicated from the game so that we can keep track of the image
     /// for each round.
     pub image_url: String,
+    /// The prompt for the round, if any
+    pub prompt: Option<String>,
+    /// The mode for the round
+    pub mode: GameMode,
     /// Whether or not the answers for the round have been closed
     ///
     /// This is used to determine if the game master can reveal the answers
@@ -88,11 +279,47 @@ pub struct Round {
     pub answers_closed: bool,
     /// Contains the answers for the round
     ///
-    /// The key is the username of the player
-    /// and the value is a tuple containing the answer and the number of likes.
+    /// A player may have several answers in a round, depending on the
+    /// `max_answers_per_round` setting of the game. Answers are ordered by
+    /// the time they were given until the answers are closed, and by the
+    /// reveal order afterwards.
     pub answers: Vec<Answer>,
+    /// The order in which the answers are revealed
+    ///
+    /// This is shuffled by the server when the answers are closed so that
+    /// every client sees the same sequence, and so that the order does not
+    /// give away who answered first.
+    pub reveal_order: Vec<Uuid>,
+    /// Whether the server is currently revealing the answers automatically
+    pub revealing: bool,
+    /// Whether revealing the answers was suspended by a pause
+    ///
+    /// The reveal sequence continues where it left off when the game resumes.
+    pub reveal_suspended: bool,
     /// Contains the user that was selected as the winner for the round
     pub round_winner: Option<Uuid>,
+    /// Contains the answer that was selected as the winner for the round
+    ///
+    /// This is needed to tell which answer won when the winner gave several.
+    pub winning_answer: Option<Uuid>,
+    /// Whether the round has ended
+    ///
+    /// Guess the answer rounds may end without a winner.
+    pub ended: bool,
+}
+
+impl Round {
+    /// Sorts the answers by the reveal order, keeping any answers missing from
+    /// the order at the end in the order they were given.
+    pub fn sort_answers_by_reveal_order(&mut self) {
+        let reveal_order = &self.reveal_order;
+        self.answers.sort_by_key(|a| {
+            reveal_order
+                .iter()
+                .position(|id| *id == a.id)
+                .unwrap_or(reveal_order.len())
+        });
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -100,10 +327,18 @@ pub struct Round {
 pub struct Answer {
     pub id: Uuid,
     pub round_id: Uuid,
-    pub player_id: Uuid,
+    /// The player that gave the answer
+    ///
+    /// This is only unset once the answer has been projected for a recipient
+    /// that is not allowed to know who wrote it yet.
+    pub player_id: Option<Uuid>,
     pub value: String,
     pub likes: i32,
     pub shown: bool,
+    /// Whether the answer matched the answer key
+    ///
+    /// This is only set for answers to guess the answer rounds.
+    pub correct: Option<bool>,
 }
 
 /// The state of the game at a given point in time
@@ -127,6 +362,22 @@ pub struct GameState {
     /// This is used to determine if the game master can reveal the answers
     /// for the round.
     pub answers_closed: bool,
+    /// The number of answers each player may give per round
+    pub max_answers_per_round: i32,
+    /// Whether the server is currently revealing the answers automatically
+    pub revealing: bool,
+    /// Whether the owner or a co-host is connected to the game
+    ///
+    /// Players are told when the host has dropped so they know to wait.
+    pub host_active: bool,
+    /// Whether the game has been paused by the host
+    ///
+    /// Players may not act while the game is paused.
+    pub paused: bool,
+    /// Ready players needed before the game can start without forcing it
+    ///
+    /// Whether each player is ready is kept on the players.
+    pub min_players: i32,
     /// The status of the game
     ///
     /// Options are:
@@ -147,16 +398,47 @@ pub struct GameState {
     ///
     /// This is only set if the game has started and has not finished yet.
     pub image_url: Option<String>,
+    /// The prompt for the round
+    ///
+    /// This is only set if the round has a prompt.
+    pub prompt: Option<String>,
+    /// The mode for the round
+    ///
+    /// Falls back to the mode of the game before the first round starts.
+    pub mode: GameMode,
     /// The answers for the round
+    ///
+    /// Once projected for a player or observer this only contains their own
+    /// answer and the answers that have been revealed.
     pub answers: Vec<Answer>,
+    /// The number of answers given for the round
+    ///
+    /// This is kept separately from `answers` so that clients can tell how
+    /// many answers are still waiting to be revealed.
+    pub answer_count: usize,
     /// Winner of the round
     ///
     /// The username of the player that was selected as the winner for the round.
     pub round_winner: Option<Player>,
+    /// The answer that was selected as the winner for the round
+    pub winning_answer: Option<Uuid>,
+    /// Whether the round has ended
+    pub round_ended: bool,
+    /// The expected answer for a guess the answer round
+    ///
+    /// Once projected for a player or observer this is only set after the
+    /// round has ended.
+    pub expected_answer: Option<String>,
     /// The scores for the game
     ///
     /// The key is the username of the player and the value is the score.
     pub scores: HashMap<String, i32>,
+    /// The points each player was awarded in the current round
+    ///
+    /// These are taken off the scores projected for players and observers
+    /// until the round has ended, and are never sent to clients.
+    #[serde(skip)]
+    pub round_scores: HashMap<Uuid, i32>,
     /// The winner of the game
     ///
     /// The username of the player that was selected as the winner for the game.
@@ -168,17 +450,74 @@ pub struct GameState {
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "type", content = "player", rename_all = "camelCase")]
 pub enum PlayerType {
+    /// The owner or a co-host
     GameMaster,
+    /// Sees every answer and may reveal or remove them, but does not play
+    Moderator,
     #[serde(rename_all = "camelCase")]
-    Player {
-        id: Uuid,
-        display_name: String,
-    },
+    Player { id: Uuid, display_name: String },
     #[serde(rename_all = "camelCase")]
-    Observer {
-        id: Uuid,
-        display_name: String,
-    },
+    Observer { id: Uuid, display_name: String },
+}
+
+impl GameState {
+    /// Projects the state for the given recipient
+    ///
+    /// The game master and moderators receive the full state. Players and
+    /// observers only receive their own answer and the answers that have been
+    /// revealed, and the authors of other answers, the expected answer and
+    /// whether answers are correct are hidden until the round has ended.
+    pub fn for_recipient(&self, recipient: &PlayerType) -> GameState {
+        let recipient_id = match recipient {
+            PlayerType::GameMaster | PlayerType::Moderator => return self.clone(),
+            PlayerType::Player { id, .. } | PlayerType::Observer { id, .. } => *id,
+        };
+        let round_ended = self.round_ended;
+
+        let answers = self
+            .answers
+            .iter()
+            .filter(|a| a.shown || a.player_id == Some(recipient_id))
+            .cloned()
+            .map(|mut a| {
+                if !round_ended {
+                    a.correct = None;
+                    if a.player_id != Some(recipient_id) {
+                        a.player_id = None;
+                    }
+                }
+                a
+            })
+            .collect();
+
+        // Answers are scored as they are given, so the scores would tell
+        // players whose answers are right, their own included, before the
+        // round has ended
+        let mut players = self.players.clone();
+        let mut scores = self.scores.clone();
+        if !round_ended {
+            for player in players.iter_mut() {
+                let points = self.round_scores.get(&player.id).copied().unwrap_or(0);
+                player.score -= points;
+                if let Some(score) = scores.get_mut(&player.username) {
+                    *score -= points;
+                }
+            }
+        }
+
+        let expected_answer = match round_ended {
+            true => self.expected_answer.clone(),
+            false => None,
+        };
+
+        GameState {
+            answers,
+            expected_answer,
+            players,
+            scores,
+            ..self.clone()
+        }
+    }
 }
 
 /// The message types that can be sent to modify the game state
@@ -197,14 +536,53 @@ pub enum GameAction {
     ///
     /// Update all the players to inform them that the next round is
     /// starting, this should cause the image to be updated for all players.
+    /// The first round only starts once enough players are ready, unless the
+    /// game master forces it.
     #[serde(rename_all = "camelCase")]
-    StartRound { round: i32 },
+    StartRound {
+        round: i32,
+        #[serde(default)]
+        force: bool,
+    },
+    /// Pause
+    ///
+    /// The game master has paused the game. Players may not act and the
+    /// answers stop being revealed automatically until the game resumes.
+    Pause,
+    /// Resume
+    ///
+    /// The game master has resumed a paused game at the round it was paused
+    /// in.
+    Resume,
+    /// Set ready
+    ///
+    /// A player has marked themselves ready, or no longer ready, for the game
+    /// to start.
+    #[serde(rename_all = "camelCase")]
+    SetReady { ready: bool },
     /// User answer
     ///
     /// A user has given an answer to the current round, we will
-    /// update all the players to inform them of the answer.
+    /// update all the players to inform them of the answer. When an answer id
+    /// is given, that answer is edited instead. If the user may only give one
+    /// answer per round, their previous answer is replaced. Either way the
+    /// answers must not have been closed yet.
+    #[serde(rename_all = "camelCase")]
+    UserAnswer {
+        round_id: Uuid,
+        answer: String,
+        answer_id: Option<Uuid>,
+    },
+    /// Withdraw answer
+    ///
+    /// A user has taken back one of their answers to the current round before
+    /// the answers were closed. Without an answer id all of their answers for
+    /// the round are withdrawn.
     #[serde(rename_all = "camelCase")]
-    UserAnswer { round_id: Uuid, answer: String },
+    WithdrawAnswer {
+        round_id: Uuid,
+        answer_id: Option<Uuid>,
+    },
     /// Close answers
     ///
     /// The game master has closed the answers for the round, we will
@@ -218,6 +596,13 @@ pub enum GameAction {
     /// answer at a time to the players.
     #[serde(rename_all = "camelCase")]
     RevealAnswer { answer_id: Uuid },
+    /// Reveal all
+    ///
+    /// The game master has asked the server to reveal the remaining answers
+    /// in the reveal order, one at a time with the game's reveal interval in
+    /// between. The answers are closed first if they are still open.
+    #[serde(rename_all = "camelCase")]
+    RevealAll { round_id: Uuid },
     /// Like answer
     ///
     /// A user has liked an answer, we will update all the players
@@ -226,11 +611,58 @@ pub enum GameAction {
     LikeAnswer { answer_id: Uuid },
     /// End round
     ///
-    /// The game master has selected a winner and the round is over.
+    /// The game master has selected a winner and the round is over. The
+    /// winning answer is optional, but should be given when the winner may
+    /// have answered more than once. Guess the answer rounds are scored
+    /// automatically, so the winner is optional there.
     #[serde(rename_all = "camelCase")]
-    EndRound { round_id: Uuid, winner: String },
+    EndRound {
+        round_id: Uuid,
+        winner: Option<String>,
+        answer_id: Option<Uuid>,
+    },
     /// End game
     EndGame,
+    /// Request promotion
+    ///
+    /// An observer has asked the game master to let them play.
+    RequestPromotion,
+    /// Promote player
+    ///
+    /// The game master has promoted an observer to a player. Depending on the
+    /// game's late join setting, they play from the next round or start with
+    /// a catch-up score.
+    #[serde(rename_all = "camelCase")]
+    PromotePlayer { player_id: Uuid },
+    /// Remove answer
+    ///
+    /// A moderator or host has removed an inappropriate answer from the round.
+    #[serde(rename_all = "camelCase")]
+    RemoveAnswer { answer_id: Uuid },
+}
+
+impl GameAction {
+    /// The name of the action, as sent in the `type` field of messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameAction::PlayerJoin { .. } => "playerJoin",
+            GameAction::StartRound { .. } => "startRound",
+            GameAction::Pause => "pause",
+            GameAction::Resume => "resume",
+            GameAction::SetReady { .. } => "setReady",
+            GameAction::UserAnswer { .. } => "userAnswer",
+            GameAction::WithdrawAnswer { .. } => "withdrawAnswer",
+            GameAction::CloseAnswers { .. } => "closeAnswers",
+            GameAction::RevealAnswer { .. } => "revealAnswer",
+            GameAction::RevealAll { .. } => "revealAll",
+            GameAction::LikeAnswer { .. } => "likeAnswer",
+            GameAction::EndRound { .. } => "endRound",
+            GameAction::EndGame => "endGame",
+            GameAction::RequestPromotion => "requestPromotion",
+            GameAction::PromotePlayer { .. } => "promotePlayer",
+            GameAction::RemoveAnswer { .. } => "removeAnswer",
+        }
+    }
 }
 
 /// The message types that can be sent to the players to update clients
@@ -255,12 +687,48 @@ pub enum GameMessage {
     StateChange {
         state: GameState,
     },
+    /// The server is shutting down and is about to close the socket, the
+    /// client should reconnect after the given number of seconds.
+    #[serde(rename_all = "camelCase")]
+    Reconnect {
+        retry_after_seconds: u64,
+    },
+    /// The client is sending messages too fast, so its messages are dropped
+    /// until it waits for the given number of milliseconds.
+    #[serde(rename_all = "camelCase")]
+    RateLimited {
+        retry_after_ms: u64,
+    },
+    /// The user no longer helps run the game, so the sockets they have open
+    /// to do so are closed. It is never sent to clients.
+    #[serde(skip)]
+    MemberRemoved {
+        user_id: Uuid,
+    },
+}
+
+impl GameMessage {
+    /// Projects the message for the given recipient
+    ///
+    /// See [`GameState::for_recipient`] for what is hidden from whom.
+    pub fn for_recipient(&self, recipient: &PlayerType) -> GameMessage {
+        match self {
+            GameMessage::StateChange { state } => GameMessage::StateChange {
+                state: state.for_recipient(recipient),
+            },
+            message => message.clone(),
+        }
+    }
 }
 
+/// A message sent to every socket connected to a game
+///
+/// The message is kept unserialized so that each socket can project it for
+/// its own recipient before sending it.
 #[derive(Debug, Clone)]
 pub struct GameBroadcast {
     pub game_id: Uuid,
-    pub message: String,
+    pub message: GameMessage,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -269,6 +737,16 @@ pub struct NewGame {
     pub user_id: Uuid,
     pub name: String,
     pub image_urls: Vec<String>,
+    pub prompts: Vec<String>,
+    pub mode: GameMode,
+    pub max_answers_per_round: i32,
+    pub auto_reveal: bool,
+    pub reveal_interval_seconds: i32,
+    pub answer_tolerance: i32,
+    pub answer_keys: Vec<AnswerKey>,
+    pub late_join: LateJoin,
+    /// Ready players needed before the game can start without forcing it
+    pub min_players: i32,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -276,6 +754,16 @@ pub struct NewGame {
 pub struct UpdateGame {
     pub name: String,
     pub image_urls: Vec<String>,
+    pub prompts: Vec<String>,
+    pub mode: GameMode,
+    pub max_answers_per_round: i32,
+    pub auto_reveal: bool,
+    pub reveal_interval_seconds: i32,
+    pub answer_tolerance: i32,
+    pub answer_keys: Vec<AnswerKey>,
+    pub late_join: LateJoin,
+    /// Ready players needed before the game can start without forcing it
+    pub min_players: i32,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -285,6 +773,16 @@ pub struct GameFilter {
     pub status: Option<GameStatus>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewPlayer {
+    pub game_id: Uuid,
+    pub username: String,
+    pub is_observer: bool,
+    pub score: i32,
+    pub first_round: Option<i32>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NewRound {
@@ -296,6 +794,10 @@ pub struct NewRound {
     /// This is duplicated from the game so that we can keep track of the image
     /// for each round.
     pub image_url: String,
+    /// The prompt for the round, if any
+    pub prompt: Option<String>,
+    /// The mode for the round
+    pub mode: GameMode,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -304,4 +806,308 @@ pub struct NewAnswer {
     pub player_id: Uuid,
     pub round_id: Uuid,
     pub value: String,
+    pub correct: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(player_id: Uuid, shown: bool) -> Answer {
+        Answer {
+            id: Uuid::from_u128(player_id.as_u128() + 100),
+            round_id: Uuid::nil(),
+            player_id: Some(player_id),
+            value: "answer".into(),
+            likes: 0,
+            shown,
+            correct: None,
+        }
+    }
+
+    fn state(answers: Vec<Answer>) -> GameState {
+        GameState {
+            game_id: Uuid::nil(),
+            name: "Name that test".into(),
+            round_id: Some(Uuid::nil()),
+            last_round: false,
+            answers_closed: true,
+            max_answers_per_round: 1,
+            revealing: false,
+            host_active: true,
+            paused: false,
+            min_players: 1,
+            status: GameStatus::Started,
+            players: vec![],
+            round_number: Some(1),
+            image_url: None,
+            prompt: None,
+            mode: GameMode::Caption,
+            answer_count: answers.len(),
+            answers,
+            round_winner: None,
+            winning_answer: None,
+            round_ended: false,
+            expected_answer: Some("expected".into()),
+            scores: HashMap::new(),
+            round_scores: HashMap::new(),
+            game_winner: None,
+        }
+    }
+
+    fn player(id: Uuid) -> PlayerType {
+        PlayerType::Player {
+            id,
+            display_name: "player".into(),
+        }
+    }
+
+    #[test]
+    fn answers_are_sorted_by_reveal_order() {
+        let answers = vec![
+            answer(Uuid::from_u128(1), false),
+            answer(Uuid::from_u128(2), false),
+            answer(Uuid::from_u128(3), false),
+        ];
+        let mut round = Round {
+            id: Uuid::nil(),
+            game_id: Uuid::nil(),
+            round_number: 1,
+            image_url: "image".into(),
+            prompt: None,
+            mode: GameMode::Caption,
+            answers_closed: true,
+            reveal_order: vec![answers[2].id, answers[0].id],
+            answers: answers.clone(),
+            revealing: false,
+            reveal_suspended: false,
+            round_winner: None,
+            winning_answer: None,
+            ended: false,
+        };
+
+        round.sort_answers_by_reveal_order();
+
+        let ids: Vec<Uuid> = round.answers.iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec![answers[2].id, answers[0].id, answers[1].id]);
+    }
+
+    #[test]
+    fn game_master_receives_every_answer() {
+        let state = state(vec![
+            answer(Uuid::from_u128(1), false),
+            answer(Uuid::from_u128(2), true),
+        ]);
+
+        let projected = state.for_recipient(&PlayerType::GameMaster);
+
+        assert_eq!(projected.answers.len(), 2);
+        assert!(projected.answers.iter().all(|a| a.player_id.is_some()));
+    }
+
+    #[test]
+    fn players_only_receive_their_own_and_revealed_answers() {
+        let me = Uuid::from_u128(1);
+        let state = state(vec![
+            answer(me, false),
+            answer(Uuid::from_u128(2), false),
+            answer(Uuid::from_u128(3), true),
+        ]);
+
+        let projected = state.for_recipient(&player(me));
+
+        assert_eq!(projected.answer_count, 3);
+        assert_eq!(projected.answers.len(), 2);
+        assert_eq!(projected.answers[0].player_id, Some(me));
+        assert_eq!(projected.answers[1].player_id, None);
+    }
+
+    #[test]
+    fn authors_are_revealed_once_the_round_has_ended() {
+        let author = Uuid::from_u128(1);
+        let mut state = state(vec![answer(author, true)]);
+        state.round_winner = Some(Player {
+            id: author,
+            game_id: Uuid::nil(),
+            username: "author".into(),
+            active: true,
+            is_observer: false,
+            score: 1,
+            first_round: None,
+            promotion_requested: false,
+            ready: false,
+        });
+        state.round_ended = true;
+
+        let projected = state.for_recipient(&PlayerType::Observer {
+            id: Uuid::from_u128(2),
+            display_name: "observer".into(),
+        });
+
+        assert_eq!(projected.answers[0].player_id, Some(author));
+        assert_eq!(projected.expected_answer.as_deref(), Some("expected"));
+    }
+
+    #[test]
+    fn correct_answers_and_their_scores_are_hidden_until_the_round_has_ended() {
+        let me = Uuid::from_u128(1);
+        let mut correct = answer(me, false);
+        correct.correct = Some(true);
+        let mut state = state(vec![correct]);
+        state.mode = GameMode::GuessTheAnswer;
+        state.players = vec![Player {
+            id: me,
+            game_id: Uuid::nil(),
+            username: "me".into(),
+            active: true,
+            is_observer: false,
+            score: 3,
+            first_round: None,
+            promotion_requested: false,
+            ready: false,
+        }];
+        state.scores = HashMap::from([("me".to_string(), 3)]);
+        state.round_scores = HashMap::from([(me, 1)]);
+
+        let projected = state.for_recipient(&player(me));
+        assert_eq!(projected.answers[0].correct, None);
+        assert_eq!(projected.players[0].score, 2);
+        assert_eq!(projected.scores["me"], 2);
+        let observed = state.for_recipient(&PlayerType::Observer {
+            id: Uuid::from_u128(2),
+            display_name: "observer".into(),
+        });
+        assert_eq!(observed.scores["me"], 2);
+
+        state.round_ended = true;
+        let projected = state.for_recipient(&player(me));
+        assert_eq!(projected.answers[0].correct, Some(true));
+        assert_eq!(projected.scores["me"], 3);
+    }
+
+    #[test]
+    fn expected_answer_is_hidden_until_the_round_has_ended() {
+        let state = state(vec![]);
+
+        let projected = state.for_recipient(&player(Uuid::from_u128(1)));
+
+        assert_eq!(projected.expected_answer, None);
+        assert_eq!(
+            state
+                .for_recipient(&PlayerType::GameMaster)
+                .expected_answer
+                .as_deref(),
+            Some("expected")
+        );
+    }
+
+    fn game(status: GameStatus, late_join: LateJoin, scores: &[(i32, bool)]) -> Game {
+        Game {
+            id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            members: vec![],
+            host_active: true,
+            paused: false,
+            name: "Name that test".into(),
+            image_urls: vec!["one".into(), "two".into(), "three".into()],
+            prompts: vec![],
+            mode: GameMode::Caption,
+            max_answers_per_round: 1,
+            auto_reveal: false,
+            reveal_interval_seconds: 3,
+            answer_tolerance: 1,
+            answer_keys: vec![],
+            late_join,
+            min_players: 1,
+            rounds: vec![],
+            players: scores
+                .iter()
+                .enumerate()
+                .map(|(i, (score, is_observer))| Player {
+                    id: Uuid::from_u128(i as u128),
+                    game_id: Uuid::nil(),
+                    username: format!("player {}", i),
+                    active: true,
+                    is_observer: *is_observer,
+                    score: *score,
+                    first_round: None,
+                    promotion_requested: false,
+                    ready: false,
+                })
+                .collect(),
+            scores: HashMap::new(),
+            status,
+            winner: None,
+        }
+    }
+
+    #[test]
+    fn players_joining_before_the_game_starts_play_every_round() {
+        let game = game(GameStatus::Pending, LateJoin::NextRound, &[(0, false)]);
+
+        assert_eq!(game.late_join_terms(), (None, 0));
+    }
+
+    #[test]
+    fn late_joiners_play_from_the_next_round() {
+        let mut game = game(GameStatus::Started, LateJoin::NextRound, &[(2, false)]);
+        game.rounds.push(Round {
+            id: Uuid::nil(),
+            game_id: Uuid::nil(),
+            round_number: 1,
+            image_url: "one".into(),
+            prompt: None,
+            mode: GameMode::Caption,
+            answers_closed: false,
+            answers: vec![],
+            reveal_order: vec![],
+            revealing: false,
+            reveal_suspended: false,
+            round_winner: None,
+            winning_answer: None,
+            ended: false,
+        });
+
+        assert_eq!(game.late_join_terms(), (Some(2), 0));
+    }
+
+    #[test]
+    fn late_joiners_catch_up_to_the_lowest_player_score() {
+        let game = game(
+            GameStatus::Started,
+            LateJoin::CatchUp,
+            &[(3, false), (2, false), (0, true)],
+        );
+
+        assert_eq!(game.late_join_terms(), (None, 2));
+    }
+
+    #[test]
+    fn only_connected_players_count_as_ready() {
+        let mut game = game(
+            GameStatus::Pending,
+            LateJoin::Observer,
+            &[(0, false), (0, false), (0, false), (0, true)],
+        );
+        game.players.iter_mut().for_each(|p| p.ready = true);
+        game.players[1].active = false;
+
+        assert_eq!(game.ready_player_count(), 2);
+    }
+
+    #[test]
+    fn action_names_match_the_message_type() {
+        let actions = [
+            GameAction::EndGame,
+            GameAction::SetReady { ready: true },
+            GameAction::RemoveAnswer {
+                answer_id: Uuid::nil(),
+            },
+        ];
+
+        for action in actions {
+            let message = serde_json::to_value(&action).unwrap();
+            assert_eq!(message["type"], action.name());
+        }
+    }
 }
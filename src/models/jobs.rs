@@ -0,0 +1,26 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "job_run_status", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum JobRunStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A single run of a background job
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job_name: String,
+    pub status: JobRunStatus,
+    /// The error the run failed with, if it failed
+    pub error: Option<String>,
+    pub started: NaiveDateTime,
+    /// When the run ended, unset while it is still running
+    pub finished: Option<NaiveDateTime>,
+}
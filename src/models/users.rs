@@ -1,17 +1,23 @@
+use std::fmt::{Debug, Formatter};
+
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+use crate::redact::Redacted;
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct NewUser {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+#[derive(Clone, Deserialize, FromRow, Serialize)]
 pub struct User {
     pub id: Uuid,
     pub email: String,
+    /// The argon2 hash of the password, never sent to clients
+    #[serde(skip_serializing)]
     pub password: String,
 }
 
@@ -20,8 +26,57 @@ pub struct UserFilter {
     pub email: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct UserUpdate {
     pub email: String,
     pub password: String,
 }
+
+impl Debug for NewUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewUser")
+            .field("email", &self.email)
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
+impl Debug for User {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("email", &self.email)
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
+impl Debug for UserUpdate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserUpdate")
+            .field("email", &self.email)
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_redacted() {
+        let user = User {
+            id: Uuid::nil(),
+            email: "host@example.com".into(),
+            password: "$argon2id$v=19$secret".into(),
+        };
+
+        let logged = format!("{:?} {:#?}", user, user);
+        let sent = serde_json::to_string(&user).unwrap();
+
+        assert!(!logged.contains("argon2"));
+        assert!(logged.contains("host@example.com"));
+        assert!(!sent.contains("argon2"));
+    }
+}
@@ -0,0 +1,19 @@
+//! Keeps secrets out of the logs
+//!
+//! Types holding secrets, such as password hashes and the session secret,
+//! implement `Debug` by hand and print [`Redacted`] in place of the secret, so
+//! that logging them with `?value` or `{:?}` is always safe.
+
+use std::fmt::{Debug, Formatter, Result};
+
+/// What is printed in place of a secret
+pub const REDACTED: &str = "[redacted]";
+
+/// Stands in for a secret in `Debug` output
+pub struct Redacted;
+
+impl Debug for Redacted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(REDACTED)
+    }
+}
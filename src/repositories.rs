@@ -1,2 +1,6 @@
+pub mod audit;
 pub mod games;
+pub mod health;
+pub mod identities;
+pub mod jobs;
 pub mod users;
@@ -0,0 +1,57 @@
+use sqlx::PgPool;
+
+use crate::{
+    error::AppResult,
+    models::audit::{AuthEvent, AuthEventFilter, AuthEventKind, NewAuthEvent},
+};
+
+/// The audit log of authentication events
+#[derive(Clone, Debug)]
+pub struct AuditRepo {
+    client: PgPool,
+}
+
+impl AuditRepo {
+    pub fn new(client: PgPool) -> Self {
+        Self { client }
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn record(&self, event: NewAuthEvent) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO auth_events (user_id, email, kind, ip)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            event.user_id,
+            event.email,
+            event.kind as AuthEventKind,
+            event.ip.map(|ip| ip.to_string())
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the most recent events, optionally for a single email or account
+    #[tracing::instrument(skip_all)]
+    pub async fn list(&self, filter: AuthEventFilter, limit: i64) -> AppResult<Vec<AuthEvent>> {
+        Ok(sqlx::query_as!(
+            AuthEvent,
+            r#"
+            SELECT id, user_id, email, kind as "kind: AuthEventKind", ip, created
+            FROM auth_events
+            WHERE ($1::varchar IS NULL OR email = $1::varchar)
+                AND ($2::uuid IS NULL OR user_id = $2::uuid)
+            ORDER BY created DESC
+            LIMIT $3
+            "#,
+            filter.email,
+            filter.user_id,
+            limit
+        )
+        .fetch_all(&self.client)
+        .await?)
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, vec};
+use std::{collections::HashMap, time::Duration, vec};
 
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -6,8 +6,8 @@ use uuid::Uuid;
 use crate::{
     error::{AppError, AppResult},
     models::games::{
-        Answer, Game, GameFilter, GameState, GameStatus, NewAnswer, NewGame, NewRound, Player,
-        Round, UpdateGame,
+        Answer, AnswerKey, Game, GameFilter, GameMember, GameMode, GameRole, GameState, GameStatus,
+        LateJoin, NewAnswer, NewGame, NewPlayer, NewRound, Player, Round, UpdateGame,
     },
 };
 
@@ -25,29 +25,51 @@ impl GameRepo {
     // Basic CRUD operations
     // -------------------------------------------------------------------------
 
+    #[tracing::instrument(skip_all)]
     pub async fn insert(&self, new_game: NewGame) -> AppResult<Game> {
         let game_id = sqlx::query!(
             r#"
-            INSERT INTO games (user_id, name, image_urls)
-            VALUES ($1, $2, $3)
+            INSERT INTO games (
+                user_id, name, image_urls, max_answers_per_round, auto_reveal,
+                reveal_interval_seconds, prompts, mode, answer_tolerance, late_join,
+                min_players
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             RETURNING id
             "#,
             new_game.user_id,
             new_game.name,
-            &new_game.image_urls
+            &new_game.image_urls,
+            new_game.max_answers_per_round,
+            new_game.auto_reveal,
+            new_game.reveal_interval_seconds,
+            &new_game.prompts,
+            new_game.mode as GameMode,
+            new_game.answer_tolerance,
+            new_game.late_join as LateJoin,
+            new_game.min_players
         )
         .fetch_one(&self.client)
         .await?
         .id;
 
+        self.set_answer_keys(&game_id, &new_game.answer_keys)
+            .await?;
+
         Ok(self.get(&game_id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get(&self, id: &Uuid) -> AppResult<Game> {
         let mut game: Game = sqlx::query_as!(
             GameRow,
             r#"
-            SELECT id, user_id, name, image_urls, status as "status: GameStatus", winner
+            SELECT
+                id, user_id, host_active, paused, name, image_urls, prompts,
+                mode as "mode: GameMode",
+                max_answers_per_round, auto_reveal, reveal_interval_seconds,
+                answer_tolerance, late_join as "late_join: LateJoin", min_players,
+                status as "status: GameStatus", winner
             FROM games
             WHERE id = $1
             "#,
@@ -59,6 +81,18 @@ impl GameRepo {
 
         game.rounds = self.get_rounds_for_game(&id).await?;
         game.players = self.get_players_for_game(&id).await?;
+        game.members = self
+            .get_members_for_games(&vec![*id])
+            .await?
+            .into_iter()
+            .map(|(_, m)| m)
+            .collect();
+        game.answer_keys = self
+            .get_answer_keys_for_games(&vec![*id])
+            .await?
+            .into_iter()
+            .map(|(_, k)| k)
+            .collect();
 
         self.get_answers_for_game(&id)
             .await?
@@ -68,6 +102,9 @@ impl GameRepo {
                     round.answers.push(a);
                 }
             });
+        game.rounds
+            .iter_mut()
+            .for_each(|r| r.sort_answers_by_reveal_order());
 
         game.players
             .iter()
@@ -79,11 +116,17 @@ impl GameRepo {
         Ok(game)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn list(&self, filter: GameFilter) -> AppResult<Vec<Game>> {
         let results = sqlx::query_as!(
             GameRow,
             r#"
-            SELECT id, user_id, name, image_urls, status as "status: GameStatus", winner
+            SELECT
+                id, user_id, host_active, paused, name, image_urls, prompts,
+                mode as "mode: GameMode",
+                max_answers_per_round, auto_reveal, reveal_interval_seconds,
+                answer_tolerance, late_join as "late_join: LateJoin", min_players,
+                status as "status: GameStatus", winner
             FROM games
             WHERE
                 ($1::uuid IS NULL OR user_id = $1::uuid) AND
@@ -109,6 +152,8 @@ impl GameRepo {
         let game_ids = games.iter().map(|g: &Game| g.id).collect::<Vec<Uuid>>();
         let answers = self.get_answers_for_games(&game_ids).await?;
         let players = self.get_players_for_games(&game_ids).await?;
+        let answer_keys = self.get_answer_keys_for_games(&game_ids).await?;
+        let members = self.get_members_for_games(&game_ids).await?;
         let rounds: Vec<Round> = self
             .get_rounds_for_games(&game_ids)
             .await?
@@ -119,6 +164,7 @@ impl GameRepo {
                     .filter(|a| a.round_id == r.id)
                     .cloned()
                     .collect();
+                r.sort_answers_by_reveal_order();
                 r.to_owned()
             })
             .collect();
@@ -134,28 +180,60 @@ impl GameRepo {
                 .filter(|r| r.game_id == g.id)
                 .cloned()
                 .collect();
+            g.answer_keys = answer_keys
+                .iter()
+                .filter(|(game_id, _)| *game_id == g.id)
+                .map(|(_, k)| k.clone())
+                .collect();
+            g.members = members
+                .iter()
+                .filter(|(game_id, _)| *game_id == g.id)
+                .map(|(_, m)| m.clone())
+                .collect();
         });
 
         Ok(games)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn update(&self, id: Uuid, update_game: UpdateGame) -> AppResult<Game> {
         sqlx::query!(
             r#"
             UPDATE games
-            SET name = $2, image_urls = $3
+            SET
+                name = $2,
+                image_urls = $3,
+                max_answers_per_round = $4,
+                auto_reveal = $5,
+                reveal_interval_seconds = $6,
+                prompts = $7,
+                mode = $8,
+                answer_tolerance = $9,
+                late_join = $10,
+                min_players = $11
             WHERE id = $1
             "#,
             id,
             update_game.name,
-            update_game.image_urls.as_slice()
+            update_game.image_urls.as_slice(),
+            update_game.max_answers_per_round,
+            update_game.auto_reveal,
+            update_game.reveal_interval_seconds,
+            update_game.prompts.as_slice(),
+            update_game.mode as GameMode,
+            update_game.answer_tolerance,
+            update_game.late_join as LateJoin,
+            update_game.min_players
         )
         .execute(&self.client)
         .await?;
 
+        self.set_answer_keys(&id, &update_game.answer_keys).await?;
+
         Ok(self.get(&id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn delete(&self, id: Uuid) -> AppResult<()> {
         sqlx::query!(
             r#"
@@ -169,6 +247,7 @@ impl GameRepo {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_state(&self, id: &Uuid) -> AppResult<GameState> {
         let game = self.get(&id).await?;
         let round = game.rounds.last();
@@ -179,10 +258,20 @@ impl GameRepo {
             Some(round) => round.round_number == game.image_urls.len() as i32,
         };
         let image_url = round.and_then(|r| Some(r.image_url.clone()));
+        let prompt = round.and_then(|r| r.prompt.clone());
+        let mode = round.map(|r| r.mode.clone()).unwrap_or(game.mode.clone());
         let answers = round
             .and_then(|r| Some(r.answers.clone()))
             .unwrap_or(vec![]);
+        let answer_count = answers.len();
         let answers_closed = round.and_then(|r| Some(r.answers_closed)).unwrap_or(false);
+        let revealing = round.map(|r| r.revealing).unwrap_or(false);
+        let winning_answer = round.and_then(|r| r.winning_answer);
+        let round_ended = round.map(|r| r.ended).unwrap_or(false);
+        let expected_answer = round
+            .filter(|r| r.mode == GameMode::GuessTheAnswer)
+            .and_then(|r| game.answer_key(r.round_number - 1))
+            .map(|k| k.expected_answer.clone());
         let round_winner = match round.and_then(|r| r.round_winner) {
             None => None,
             Some(winner) => game
@@ -191,6 +280,10 @@ impl GameRepo {
                 .find(|p| p.id == winner)
                 .and_then(|p| Some(p.clone())),
         };
+        let round_scores = match round_id {
+            Some(round_id) => self.round_scores(&round_id).await?,
+            None => HashMap::new(),
+        };
         let game_winner = match game.winner {
             None => None,
             Some(winner) => game
@@ -207,12 +300,24 @@ impl GameRepo {
             round_number,
             last_round,
             answers_closed,
+            max_answers_per_round: game.max_answers_per_round,
+            revealing,
+            host_active: game.host_active,
+            paused: game.paused,
+            min_players: game.min_players,
             image_url,
+            prompt,
+            mode,
             answers,
+            answer_count,
             status: game.status,
             players: game.players,
             round_winner,
+            winning_answer,
+            round_ended,
+            expected_answer,
             scores: game.scores,
+            round_scores,
             game_winner,
         })
     }
@@ -221,11 +326,14 @@ impl GameRepo {
     // Game logic
     // -------------------------------------------------------------------------
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_player(&self, id: &Uuid) -> AppResult<Player> {
         let player = sqlx::query_as!(
             Player,
             r#"
-            SELECT id, game_id, username, active, is_observer, score
+            SELECT
+                id, game_id, username, active, is_observer, score, first_round,
+                promotion_requested, ready
             FROM players
             WHERE id = $1
             "#,
@@ -237,28 +345,89 @@ impl GameRepo {
         Ok(player)
     }
 
-    pub async fn add_player(
-        &self,
-        game_id: &Uuid,
-        username: &str,
-        is_observer: &bool,
-    ) -> AppResult<Game> {
+    #[tracing::instrument(skip_all)]
+    pub async fn add_player(&self, player: NewPlayer) -> AppResult<Game> {
         sqlx::query!(
             r#"
-            INSERT INTO players (game_id, username, active, is_observer)
-            VALUES ($1, $2, true, $3)
+            INSERT INTO players (game_id, username, active, is_observer, score, first_round)
+            VALUES ($1, $2, true, $3, $4, $5)
             ON CONFLICT DO NOTHING
             "#,
-            game_id,
-            username,
-            is_observer
+            player.game_id,
+            player.username,
+            player.is_observer,
+            player.score,
+            player.first_round
         )
         .execute(&self.client)
         .await?;
 
-        Ok(self.get(&game_id).await?)
+        self.get(&player.game_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn set_ready(&self, player_id: &Uuid, ready: bool) -> AppResult<Game> {
+        let game_id = sqlx::query!(
+            r#"
+            UPDATE players
+            SET ready = $2
+            WHERE id = $1
+            RETURNING game_id
+            "#,
+            player_id,
+            ready
+        )
+        .fetch_one(&self.client)
+        .await?
+        .game_id;
+
+        self.get(&game_id).await
     }
 
+    #[tracing::instrument(skip_all)]
+    pub async fn request_promotion(&self, player_id: &Uuid) -> AppResult<Game> {
+        let game_id = sqlx::query!(
+            r#"
+            UPDATE players
+            SET promotion_requested = true
+            WHERE id = $1 AND is_observer
+            RETURNING game_id
+            "#,
+            player_id
+        )
+        .fetch_one(&self.client)
+        .await?
+        .game_id;
+
+        self.get(&game_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn promote_player(
+        &self,
+        player_id: &Uuid,
+        first_round: Option<i32>,
+        score: i32,
+    ) -> AppResult<Game> {
+        let game_id = sqlx::query!(
+            r#"
+            UPDATE players
+            SET is_observer = false, promotion_requested = false, first_round = $2, score = $3
+            WHERE id = $1
+            RETURNING game_id
+            "#,
+            player_id,
+            first_round,
+            score
+        )
+        .fetch_one(&self.client)
+        .await?
+        .game_id;
+
+        self.get(&game_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn remove_player(&self, id: &Uuid) -> AppResult<()> {
         sqlx::query!(
             r#"
@@ -273,6 +442,7 @@ impl GameRepo {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn mark_player_active(&self, id: &Uuid) -> AppResult<Game> {
         let game_id = sqlx::query!(
             r#"
@@ -290,6 +460,7 @@ impl GameRepo {
         Ok(self.get(&game_id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn mark_player_inactive(&self, id: &Uuid) -> AppResult<Game> {
         let game_id = sqlx::query!(
             r#"
@@ -307,11 +478,204 @@ impl GameRepo {
         Ok(self.get(&game_id).await?)
     }
 
-    pub async fn start(&self, game_id: &Uuid) -> AppResult<Game> {
+    /// Records that this server has the players and the hosts of the games
+    /// connected
+    #[tracing::instrument(skip_all)]
+    pub async fn record_presence(&self, player_ids: &[Uuid], game_ids: &[Uuid]) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO player_presence (player_id)
+            SELECT id FROM players WHERE id = ANY($1)
+            ON CONFLICT (player_id) DO UPDATE SET seen = now()
+            "#,
+            player_ids
+        )
+        .execute(&self.client)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO host_presence (game_id)
+            SELECT id FROM games WHERE id = ANY($1)
+            ON CONFLICT (game_id) DO UPDATE SET seen = now()
+            "#,
+            game_ids
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether any server has recorded the player as connected within the
+    /// period
+    #[tracing::instrument(skip_all)]
+    pub async fn is_player_present(&self, player_id: &Uuid, within: Duration) -> AppResult<bool> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT player_id
+            FROM player_presence
+            WHERE player_id = $1 AND seen >= now() - make_interval(secs => $2)
+            "#,
+            player_id,
+            within.as_secs_f64()
+        )
+        .fetch_optional(&self.client)
+        .await?
+        .is_some())
+    }
+
+    /// Whether any server has recorded a host of the game as connected within
+    /// the period
+    #[tracing::instrument(skip_all)]
+    pub async fn is_host_present(&self, game_id: &Uuid, within: Duration) -> AppResult<bool> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT game_id
+            FROM host_presence
+            WHERE game_id = $1 AND seen >= now() - make_interval(secs => $2)
+            "#,
+            game_id,
+            within.as_secs_f64()
+        )
+        .fetch_optional(&self.client)
+        .await?
+        .is_some())
+    }
+
+    /// Marks players inactive that no server has recorded as connected within
+    /// the timeout and that have not been updated within the grace period,
+    /// such as players left active after a restart.
+    ///
+    /// Returns the ids of the games that had players marked inactive.
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_disconnected_players_inactive(
+        &self,
+        grace_period: Duration,
+        timeout: Duration,
+    ) -> AppResult<Vec<Uuid>> {
+        let mut game_ids: Vec<Uuid> = sqlx::query!(
+            r#"
+            UPDATE players
+            SET active = false
+            WHERE
+                active AND
+                updated < now() - make_interval(secs => $1) AND
+                NOT EXISTS (
+                    SELECT 1 FROM player_presence p
+                    WHERE p.player_id = players.id
+                        AND p.seen >= now() - make_interval(secs => $2)
+                )
+            RETURNING game_id
+            "#,
+            grace_period.as_secs_f64(),
+            timeout.as_secs_f64()
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| r.game_id)
+        .collect();
+        game_ids.sort();
+        game_ids.dedup();
+
+        Ok(game_ids)
+    }
+
+    /// Marks the hosts of games inactive that no server has recorded as
+    /// connected within the timeout and that have not been updated within the
+    /// grace period.
+    ///
+    /// Returns the ids of the games that were marked.
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_disconnected_hosts_inactive(
+        &self,
+        grace_period: Duration,
+        timeout: Duration,
+    ) -> AppResult<Vec<Uuid>> {
+        Ok(sqlx::query!(
+            r#"
+            UPDATE games
+            SET host_active = false
+            WHERE
+                host_active AND
+                updated < now() - make_interval(secs => $1) AND
+                NOT EXISTS (
+                    SELECT 1 FROM host_presence p
+                    WHERE p.game_id = games.id
+                        AND p.seen >= now() - make_interval(secs => $2)
+                )
+            RETURNING id
+            "#,
+            grace_period.as_secs_f64(),
+            timeout.as_secs_f64()
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect())
+    }
+
+    /// Finds the games that have not finished and have seen no activity within
+    /// the idle period.
+    ///
+    /// Activity is any change to the game or to its rounds, players or
+    /// answers.
+    #[tracing::instrument(skip_all)]
+    pub async fn find_idle_games(&self, idle_period: Duration) -> AppResult<Vec<Uuid>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT g.id
+            FROM games g
+            WHERE
+                g.status <> 'finished'::game_status AND
+                greatest(
+                    g.updated,
+                    (SELECT max(r.updated) FROM rounds r WHERE r.game_id = g.id),
+                    (SELECT max(p.updated) FROM players p WHERE p.game_id = g.id),
+                    (
+                        SELECT max(a.updated)
+                        FROM answers a
+                        join rounds r on r.id = a.round_id
+                        WHERE r.game_id = g.id
+                    )
+                ) < now() - make_interval(secs => $1)
+            "#,
+            idle_period.as_secs_f64()
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect())
+    }
+
+    /// Deletes the games that finished longer ago than the retention period,
+    /// along with their rounds, players and answers.
+    ///
+    /// Returns the number of games deleted.
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_finished_games(&self, retention_period: Duration) -> AppResult<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM games
+            WHERE
+                status = 'finished'::game_status AND
+                updated < now() - make_interval(secs => $1)
+            "#,
+            retention_period.as_secs_f64()
+        )
+        .execute(&self.client)
+        .await?
+        .rows_affected())
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_host_active(&self, game_id: &Uuid) -> AppResult<Game> {
         sqlx::query!(
             r#"
             UPDATE games
-            SET status = 'started'::game_status
+            SET host_active = true
             WHERE id = $1
             "#,
             game_id
@@ -319,18 +683,150 @@ impl GameRepo {
         .execute(&self.client)
         .await?;
 
-        Ok(self.get(&game_id).await?)
+        self.get(game_id).await
     }
 
-    pub async fn add_round(&self, round: NewRound) -> AppResult<Game> {
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_host_inactive(&self, game_id: &Uuid) -> AppResult<Game> {
         sqlx::query!(
             r#"
-            INSERT INTO rounds (game_id, round_number, image_url)
+            UPDATE games
+            SET host_active = false
+            WHERE id = $1
+            "#,
+            game_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get(game_id).await
+    }
+
+    /// Adds a registered user to help run the game, or changes their role if
+    /// they were already invited
+    #[tracing::instrument(skip_all)]
+    pub async fn add_member(
+        &self,
+        game_id: &Uuid,
+        user_id: &Uuid,
+        role: GameRole,
+    ) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            INSERT INTO game_members (game_id, user_id, role)
             VALUES ($1, $2, $3)
+            ON CONFLICT (game_id, user_id) DO UPDATE SET role = excluded.role
+            "#,
+            game_id,
+            user_id,
+            role as GameRole
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get(game_id).await
+    }
+
+    /// Gets the role of the user in the game, if they were invited to it
+    #[tracing::instrument(skip_all)]
+    pub async fn get_member_role(
+        &self,
+        game_id: &Uuid,
+        user_id: &Uuid,
+    ) -> AppResult<Option<GameRole>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT role as "role: GameRole"
+            FROM game_members
+            WHERE game_id = $1 AND user_id = $2
+            "#,
+            game_id,
+            user_id
+        )
+        .fetch_optional(&self.client)
+        .await?
+        .map(|r| r.role))
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_member(&self, game_id: &Uuid, user_id: &Uuid) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            DELETE FROM game_members
+            WHERE game_id = $1 AND user_id = $2
+            "#,
+            game_id,
+            user_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get(game_id).await
+    }
+
+    /// Starts the game with its first round, so that a game is never started
+    /// without one
+    #[tracing::instrument(skip_all)]
+    pub async fn start(&self, first_round: NewRound) -> AppResult<Game> {
+        let mut tx = self.client.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE games
+            SET status = 'started'::game_status
+            WHERE id = $1
+            "#,
+            first_round.game_id
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO rounds (game_id, round_number, image_url, prompt, mode)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            first_round.game_id,
+            first_round.round_number,
+            first_round.image_url,
+            first_round.prompt,
+            first_round.mode as GameMode
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        self.get(&first_round.game_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn set_paused(&self, game_id: &Uuid, paused: bool) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            UPDATE games
+            SET paused = $2
+            WHERE id = $1
+            "#,
+            game_id,
+            paused
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get(game_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn add_round(&self, round: NewRound) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            INSERT INTO rounds (game_id, round_number, image_url, prompt, mode)
+            VALUES ($1, $2, $3, $4, $5)
             "#,
             round.game_id,
             round.round_number,
-            round.image_url
+            round.image_url,
+            round.prompt,
+            round.mode as GameMode
         )
         .execute(&self.client)
         .await?;
@@ -338,7 +834,13 @@ impl GameRepo {
         Ok(self.get(&round.game_id).await?)
     }
 
-    pub async fn add_answer(&self, answer: NewAnswer) -> AppResult<Game> {
+    /// Adds the answer unless the player already has the most answers
+    /// allowed in the round
+    ///
+    /// The player is locked while their answers are counted, so that answers
+    /// sent at the same time cannot go over the limit.
+    #[tracing::instrument(skip_all)]
+    pub async fn add_answer(&self, answer: NewAnswer, max_answers: i32) -> AppResult<Game> {
         let game = self.get_by_round_id(&answer.round_id).await?;
         if !game.players.iter().any(|p| p.id == answer.player_id) {
             return Err(AppError::ValidationError(
@@ -346,21 +848,103 @@ impl GameRepo {
             ));
         }
 
+        let mut tx = self.client.begin().await?;
         sqlx::query!(
             r#"
-            INSERT INTO answers (round_id, player_id, value)
-            VALUES ($1, $2, $3)
+            SELECT id FROM players WHERE id = $1 FOR UPDATE
+            "#,
+            answer.player_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let added = sqlx::query!(
+            r#"
+            INSERT INTO answers (round_id, player_id, value, correct)
+            SELECT $1, $2, $3, $4
+            WHERE (
+                SELECT count(*) FROM answers WHERE round_id = $1 AND player_id = $2
+            ) < $5
             "#,
             answer.round_id,
             answer.player_id,
-            answer.value
+            answer.value,
+            answer.correct,
+            max_answers as i64
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+
+        if added == 0 {
+            return Err(AppError::ValidationError(format!(
+                "Only {} answers are allowed per round",
+                max_answers
+            )));
+        }
+
+        Ok(self.get_by_round_id(&answer.round_id).await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn update_answer(
+        &self,
+        answer_id: &Uuid,
+        value: &str,
+        correct: Option<bool>,
+    ) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            UPDATE answers
+            SET value = $2, correct = $3
+            WHERE id = $1
+            "#,
+            answer_id,
+            value,
+            correct
         )
         .execute(&self.client)
         .await?;
 
-        Ok(self.get_by_round_id(&answer.round_id).await?)
+        self.get_by_answer_id(answer_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_answer(&self, answer_id: &Uuid) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM answers
+            WHERE id = $1
+            "#,
+            answer_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_player_answers(
+        &self,
+        round_id: &Uuid,
+        player_id: &Uuid,
+    ) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            DELETE FROM answers
+            WHERE round_id = $1 AND player_id = $2
+            "#,
+            round_id,
+            player_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get_by_round_id(round_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn increment_like(&self, answer_id: &Uuid) -> AppResult<Game> {
         sqlx::query!(
             r#"
@@ -376,6 +960,7 @@ impl GameRepo {
         Ok(self.get_by_answer_id(&answer_id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn close_answers(&self, round_id: &Uuid) -> AppResult<Game> {
         sqlx::query!(
             r#"
@@ -391,6 +976,104 @@ impl GameRepo {
         Ok(self.get_by_round_id(&round_id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
+    pub async fn set_reveal_order(
+        &self,
+        round_id: &Uuid,
+        reveal_order: &[Uuid],
+    ) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            UPDATE rounds
+            SET reveal_order = $2
+            WHERE id = $1
+            "#,
+            round_id,
+            reveal_order
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get_by_round_id(round_id).await
+    }
+
+    /// Marks the round as being revealed automatically, starting with the
+    /// next run of the reveal job
+    ///
+    /// Returns false if the round was already being revealed.
+    #[tracing::instrument(skip_all)]
+    pub async fn start_revealing(&self, round_id: &Uuid) -> AppResult<bool> {
+        let started = sqlx::query!(
+            r#"
+            UPDATE rounds
+            SET revealing = true, reveal_suspended = false, next_reveal_at = now()
+            WHERE id = $1 AND NOT revealing
+            RETURNING id
+            "#,
+            round_id
+        )
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(started.is_some())
+    }
+
+    /// Takes the rounds that are due to reveal their next answer, and
+    /// schedules the reveal after for the reveal interval of their game
+    ///
+    /// Returns the ids of the rounds along with the ids of their games.
+    #[tracing::instrument(skip_all)]
+    pub async fn take_due_reveals(&self) -> AppResult<Vec<(Uuid, Uuid)>> {
+        Ok(sqlx::query!(
+            r#"
+            UPDATE rounds r
+            SET next_reveal_at = now() + make_interval(secs => g.reveal_interval_seconds)
+            FROM games g
+            WHERE g.id = r.game_id AND r.revealing AND r.next_reveal_at <= now()
+            RETURNING r.id, r.game_id
+            "#
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r.game_id))
+        .collect())
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn stop_revealing(&self, round_id: &Uuid) -> AppResult<Game> {
+        sqlx::query!(
+            r#"
+            UPDATE rounds
+            SET revealing = false, next_reveal_at = null
+            WHERE id = $1
+            "#,
+            round_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        self.get_by_round_id(round_id).await
+    }
+
+    /// Stops revealing the answers of the round until the game is resumed
+    #[tracing::instrument(skip_all)]
+    pub async fn suspend_revealing(&self, round_id: &Uuid) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE rounds
+            SET revealing = false, reveal_suspended = true, next_reveal_at = null
+            WHERE id = $1
+            "#,
+            round_id
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn show_answer(&self, answer_id: &Uuid) -> AppResult<Game> {
         sqlx::query!(
             r#"
@@ -406,15 +1089,22 @@ impl GameRepo {
         Ok(self.get_by_answer_id(&answer_id).await?)
     }
 
-    pub async fn end_round(&self, round_id: &Uuid, winner: &Uuid) -> AppResult<Game> {
+    #[tracing::instrument(skip_all)]
+    pub async fn end_round(
+        &self,
+        round_id: &Uuid,
+        winner: Option<&Uuid>,
+        winning_answer: Option<&Uuid>,
+    ) -> AppResult<Game> {
         sqlx::query!(
             r#"
             UPDATE rounds
-            SET round_winner = $2
+            SET round_winner = $2, winning_answer = $3, ended = true
             WHERE id = $1
             "#,
             round_id,
-            winner
+            winner,
+            winning_answer
         )
         .execute(&self.client)
         .await?;
@@ -422,23 +1112,76 @@ impl GameRepo {
         Ok(self.get_by_round_id(&round_id).await?)
     }
 
-    pub async fn increment_score(&self, player_id: &Uuid) -> AppResult<Game> {
-        let game_id = sqlx::query!(
+    #[tracing::instrument(skip_all)]
+    pub async fn increment_score(&self, round_id: &Uuid, player_id: &Uuid) -> AppResult<Game> {
+        self.add_score(round_id, player_id, 1).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn decrement_score(&self, round_id: &Uuid, player_id: &Uuid) -> AppResult<Game> {
+        self.add_score(round_id, player_id, -1).await
+    }
+
+    /// Adds the points to the score of the player, which never drops below
+    /// zero, and records the points actually added against the round
+    async fn add_score(&self, round_id: &Uuid, player_id: &Uuid, points: i32) -> AppResult<Game> {
+        let mut tx = self.client.begin().await?;
+
+        let previous = sqlx::query!(
             r#"
-            UPDATE players
-            SET score = COALESCE(score, 0) + 1
-            WHERE id = $1
-            RETURNING game_id
+            SELECT score FROM players WHERE id = $1 FOR UPDATE
             "#,
             player_id
         )
-        .fetch_one(&self.client)
+        .fetch_one(&mut tx)
         .await?
-        .game_id;
+        .score;
+        let player = sqlx::query!(
+            r#"
+            UPDATE players
+            SET score = GREATEST(score + $2, 0)
+            WHERE id = $1
+            RETURNING game_id, score
+            "#,
+            player_id,
+            points
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO round_scores (round_id, player_id, points)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (round_id, player_id)
+            DO UPDATE SET points = round_scores.points + excluded.points
+            "#,
+            round_id,
+            player_id,
+            player.score - previous
+        )
+        .execute(&mut tx)
+        .await?;
 
-        Ok(self.get(&game_id).await?)
+        tx.commit().await?;
+        self.get(&player.game_id).await
     }
 
+    /// Gets the points each player was awarded in the round
+    async fn round_scores(&self, round_id: &Uuid) -> AppResult<HashMap<Uuid, i32>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT player_id, points FROM round_scores WHERE round_id = $1
+            "#,
+            round_id
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| (r.player_id, r.points))
+        .collect())
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn end(&self, game_id: &Uuid) -> AppResult<Game> {
         let game = self.get(&game_id).await?;
 
@@ -455,7 +1198,7 @@ impl GameRepo {
         sqlx::query!(
             r#"
             UPDATE games
-            SET status = 'finished'::game_status, winner = $2
+            SET status = 'finished'::game_status, winner = $2, paused = false
             WHERE id = $1
             "#,
             game_id,
@@ -475,7 +1218,11 @@ impl GameRepo {
         Ok(sqlx::query_as!(
             RoundRow,
             r#"
-            SELECT id, game_id, round_number, image_url, answers_closed, round_winner
+            SELECT
+                id, game_id, round_number, image_url, prompt, mode as "mode: GameMode",
+                answers_closed, round_winner, winning_answer, reveal_order,
+                revealing,
+                reveal_suspended, ended
             FROM rounds
             WHERE game_id = $1
             "#,
@@ -492,7 +1239,11 @@ impl GameRepo {
         Ok(sqlx::query_as!(
             RoundRow,
             r#"
-            SELECT id, game_id, round_number, image_url, answers_closed, round_winner
+            SELECT
+                id, game_id, round_number, image_url, prompt, mode as "mode: GameMode",
+                answers_closed, round_winner, winning_answer, reveal_order,
+                revealing,
+                reveal_suspended, ended
             FROM rounds
             WHERE game_id = ANY($1)
             "#,
@@ -509,7 +1260,9 @@ impl GameRepo {
         let players = sqlx::query_as!(
             Player,
             r#"
-            SELECT id, game_id, username, active, is_observer, score
+            SELECT
+                id, game_id, username, active, is_observer, score, first_round,
+                promotion_requested, ready
             FROM players
             WHERE game_id = $1
             "#,
@@ -525,7 +1278,9 @@ impl GameRepo {
         let players = sqlx::query_as!(
             Player,
             r#"
-            SELECT id, game_id, username, active, is_observer, score
+            SELECT
+                id, game_id, username, active, is_observer, score, first_round,
+                promotion_requested, ready
             FROM players
             WHERE game_id = ANY($1)
             "#,
@@ -541,11 +1296,13 @@ impl GameRepo {
         Ok(sqlx::query_as!(
             Answer,
             r#"
-            SELECT a.id, a.round_id, a.player_id, a.value, a.likes, a.shown
+            SELECT
+                a.id, a.round_id, a.player_id as "player_id?", a.value, a.likes, a.shown,
+                a.correct
             FROM answers a
             join rounds r on r.id = a.round_id
             WHERE r.game_id = $1
-            ORDER BY a.created ASC
+            ORDER BY a.created ASC, a.id ASC
             "#,
             game_id
         )
@@ -557,11 +1314,13 @@ impl GameRepo {
         Ok(sqlx::query_as!(
             Answer,
             r#"
-            SELECT a.id, a.round_id, a.player_id, a.value, a.likes, a.shown
+            SELECT
+                a.id, a.round_id, a.player_id as "player_id?", a.value, a.likes, a.shown,
+                a.correct
             FROM answers a
             join rounds r on r.id = a.round_id
             WHERE r.game_id = ANY($1)
-            ORDER BY a.created ASC
+            ORDER BY a.created ASC, a.id ASC
             "#,
             game_ids
         )
@@ -569,6 +1328,99 @@ impl GameRepo {
         .await?)
     }
 
+    /// Replaces the answer keys of the game
+    async fn set_answer_keys(&self, game_id: &Uuid, answer_keys: &[AnswerKey]) -> AppResult<()> {
+        let mut tx = self.client.begin().await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM answer_keys
+            WHERE game_id = $1
+            "#,
+            game_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        for answer_key in answer_keys {
+            sqlx::query!(
+                r#"
+                INSERT INTO answer_keys (game_id, image_index, expected_answer, aliases)
+                VALUES ($1, $2, $3, $4)
+                "#,
+                game_id,
+                answer_key.image_index,
+                answer_key.expected_answer,
+                &answer_key.aliases
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_members_for_games(
+        &self,
+        game_ids: &Vec<Uuid>,
+    ) -> AppResult<Vec<(Uuid, GameMember)>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT m.game_id, m.user_id, u.email, m.role as "role: GameRole"
+            FROM game_members m
+            join users u on u.id = m.user_id
+            WHERE m.game_id = ANY($1)
+            ORDER BY m.created ASC
+            "#,
+            game_ids
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| {
+            (
+                r.game_id,
+                GameMember {
+                    user_id: r.user_id,
+                    email: r.email,
+                    role: r.role,
+                },
+            )
+        })
+        .collect())
+    }
+
+    async fn get_answer_keys_for_games(
+        &self,
+        game_ids: &Vec<Uuid>,
+    ) -> AppResult<Vec<(Uuid, AnswerKey)>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT game_id, image_index, expected_answer, aliases
+            FROM answer_keys
+            WHERE game_id = ANY($1)
+            ORDER BY image_index ASC
+            "#,
+            game_ids
+        )
+        .fetch_all(&self.client)
+        .await?
+        .into_iter()
+        .map(|r| {
+            (
+                r.game_id,
+                AnswerKey {
+                    image_index: r.image_index,
+                    expected_answer: r.expected_answer,
+                    aliases: r.aliases,
+                },
+            )
+        })
+        .collect())
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn get_by_round_id(&self, round_id: &Uuid) -> AppResult<Game> {
         let game_id = sqlx::query!(
             r#"
@@ -585,6 +1437,7 @@ impl GameRepo {
         Ok(self.get(&game_id).await?)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_by_answer_id(&self, answer_id: &Uuid) -> AppResult<Game> {
         let game_id = sqlx::query!(
             r#"
@@ -606,8 +1459,18 @@ impl GameRepo {
 struct GameRow {
     id: Uuid,
     user_id: Uuid,
+    host_active: bool,
+    paused: bool,
     name: String,
     image_urls: Vec<String>,
+    prompts: Vec<String>,
+    mode: GameMode,
+    max_answers_per_round: i32,
+    auto_reveal: bool,
+    reveal_interval_seconds: i32,
+    answer_tolerance: i32,
+    late_join: LateJoin,
+    min_players: i32,
     status: GameStatus,
     winner: Option<Uuid>,
 }
@@ -617,8 +1480,15 @@ struct RoundRow {
     game_id: Uuid,
     round_number: i32,
     image_url: String,
+    prompt: Option<String>,
+    mode: GameMode,
     answers_closed: bool,
     round_winner: Option<Uuid>,
+    winning_answer: Option<Uuid>,
+    reveal_order: Vec<Uuid>,
+    revealing: bool,
+    reveal_suspended: bool,
+    ended: bool,
 }
 
 impl Into<Game> for GameRow {
@@ -626,8 +1496,20 @@ impl Into<Game> for GameRow {
         Game {
             id: self.id,
             user_id: self.user_id,
+            members: vec![],
+            host_active: self.host_active,
+            paused: self.paused,
             name: self.name,
             image_urls: self.image_urls,
+            prompts: self.prompts,
+            mode: self.mode,
+            max_answers_per_round: self.max_answers_per_round,
+            auto_reveal: self.auto_reveal,
+            reveal_interval_seconds: self.reveal_interval_seconds,
+            answer_tolerance: self.answer_tolerance,
+            answer_keys: vec![],
+            late_join: self.late_join,
+            min_players: self.min_players,
             players: vec![],
             rounds: vec![],
             scores: HashMap::new(),
@@ -644,9 +1526,16 @@ impl Into<Round> for RoundRow {
             game_id: self.game_id,
             round_number: self.round_number,
             image_url: self.image_url,
+            prompt: self.prompt,
+            mode: self.mode,
             answers: vec![],
             answers_closed: self.answers_closed,
             round_winner: self.round_winner,
+            winning_answer: self.winning_answer,
+            reveal_order: self.reveal_order,
+            revealing: self.revealing,
+            reveal_suspended: self.reveal_suspended,
+            ended: self.ended,
         }
     }
 }
@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+
+use sqlx::{migrate::Migrate, PgPool};
+
+use crate::error::AppResult;
+
+#[derive(Clone, Debug)]
+pub struct HealthRepo {
+    client: PgPool,
+}
+
+/// How many connections the database pool has open and how many are idle
+#[derive(Clone, Copy, Debug)]
+pub struct PoolUsage {
+    pub size: u32,
+    pub idle: usize,
+}
+
+impl HealthRepo {
+    pub fn new(client: PgPool) -> Self {
+        Self { client }
+    }
+
+    /// Checks that the database answers queries
+    #[tracing::instrument(skip_all)]
+    pub async fn ping(&self) -> AppResult<()> {
+        sqlx::query("SELECT 1").execute(&self.client).await?;
+        Ok(())
+    }
+
+    /// Counts the migrations of this build that have not been applied to the
+    /// database yet
+    ///
+    /// The migrations table is not created here, so a database that has never
+    /// been migrated is an error rather than a count.
+    #[tracing::instrument(skip_all)]
+    pub async fn pending_migrations(&self) -> AppResult<usize> {
+        let mut connection = self.client.acquire().await?;
+        let applied: HashSet<i64> = connection
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| m.version)
+            .collect();
+
+        Ok(sqlx::migrate!()
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .filter(|m| !applied.contains(&m.version))
+            .count())
+    }
+
+    pub fn pool_usage(&self) -> PoolUsage {
+        PoolUsage {
+            size: self.client.size(),
+            idle: self.client.num_idle(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::identities::{Identity, NewIdentity},
+};
+
+/// The identities at OpenID Connect providers linked to accounts
+#[derive(Clone, Debug)]
+pub struct IdentityRepo {
+    client: PgPool,
+}
+
+impl IdentityRepo {
+    pub fn new(client: PgPool) -> Self {
+        Self { client }
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn get(&self, issuer: &str, subject: &str) -> AppResult<Option<Identity>> {
+        Ok(sqlx::query_as!(
+            Identity,
+            r#"
+            SELECT id, user_id, issuer, subject, email, created
+            FROM user_identities
+            WHERE issuer = $1 AND subject = $2
+            "#,
+            issuer,
+            subject
+        )
+        .fetch_optional(&self.client)
+        .await?)
+    }
+
+    /// Links the identity to the account, failing if it already logs in to
+    /// one
+    #[tracing::instrument(skip_all)]
+    pub async fn insert(&self, identity: NewIdentity) -> AppResult<Identity> {
+        sqlx::query_as!(
+            Identity,
+            r#"
+            INSERT INTO user_identities (user_id, issuer, subject, email)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, issuer, subject, email, created
+            "#,
+            identity.user_id,
+            identity.issuer,
+            identity.subject,
+            identity.email
+        )
+        .fetch_one(&self.client)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e)
+                if e.constraint() == Some("user_identities_issuer_subject_key") =>
+            {
+                AppError::ValidationError("This identity is already linked to an account".into())
+            }
+            e => e.into(),
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn list_for_user(&self, user_id: Uuid) -> AppResult<Vec<Identity>> {
+        Ok(sqlx::query_as!(
+            Identity,
+            r#"
+            SELECT id, user_id, issuer, subject, email, created
+            FROM user_identities
+            WHERE user_id = $1
+            ORDER BY created
+            "#,
+            user_id
+        )
+        .fetch_all(&self.client)
+        .await?)
+    }
+}
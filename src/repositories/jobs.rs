@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use sqlx::{pool::PoolConnection, PgPool, Postgres};
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult,
+    models::jobs::{JobRun, JobRunStatus},
+};
+
+#[derive(Clone, Debug)]
+pub struct JobRepo {
+    client: PgPool,
+}
+
+/// A Postgres advisory lock held for a job
+///
+/// The lock belongs to the connection it was taken on, so the connection is
+/// kept until the lock is released. Should the process die, Postgres releases
+/// the lock along with the connection, and a lock dropped without being
+/// released closes its connection rather than returning it to the pool still
+/// holding the lock.
+pub struct JobLock {
+    job_name: String,
+    /// Unset once the lock has been released
+    connection: Option<PoolConnection<Postgres>>,
+}
+
+impl JobRepo {
+    pub fn new(client: PgPool) -> Self {
+        Self { client }
+    }
+
+    /// Takes the lock for the job, unless another process is already holding
+    /// it.
+    #[tracing::instrument(skip_all)]
+    pub async fn try_lock(&self, job_name: &str) -> AppResult<Option<JobLock>> {
+        let mut connection = self.client.acquire().await?;
+        let locked = sqlx::query_scalar!(
+            r#"SELECT pg_try_advisory_lock(hashtextextended($1, 0)) as "locked!""#,
+            job_name
+        )
+        .fetch_one(&mut connection)
+        .await?;
+
+        Ok(locked.then(|| JobLock {
+            job_name: job_name.to_owned(),
+            connection: Some(connection),
+        }))
+    }
+
+    /// Records that the job has started running for the occurrence of its
+    /// schedule
+    ///
+    /// Returns `None` if the occurrence was already claimed, such as by
+    /// another instance that ran the job moments before.
+    #[tracing::instrument(skip_all)]
+    pub async fn start_run(
+        &self,
+        job_name: &str,
+        scheduled_for: NaiveDateTime,
+    ) -> AppResult<Option<Uuid>> {
+        Ok(sqlx::query!(
+            r#"
+            INSERT INTO job_runs (job_name, scheduled_for)
+            VALUES ($1, $2)
+            ON CONFLICT (job_name, scheduled_for) DO NOTHING
+            RETURNING id
+            "#,
+            job_name,
+            scheduled_for
+        )
+        .fetch_optional(&self.client)
+        .await?
+        .map(|r| r.id))
+    }
+
+    /// Records the outcome of a run, along with the error if it failed
+    #[tracing::instrument(skip_all)]
+    pub async fn finish_run(&self, run_id: &Uuid, error: Option<&str>) -> AppResult<()> {
+        let status = match error {
+            Some(_) => JobRunStatus::Failed,
+            None => JobRunStatus::Succeeded,
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE job_runs
+            SET status = $2, error = $3, finished = now()
+            WHERE id = $1
+            "#,
+            run_id,
+            status as JobRunStatus,
+            error
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lists the most recent runs, optionally of a single job
+    #[tracing::instrument(skip_all)]
+    pub async fn list_runs(&self, job_name: Option<&str>, limit: i64) -> AppResult<Vec<JobRun>> {
+        Ok(sqlx::query_as!(
+            JobRun,
+            r#"
+            SELECT
+                id, job_name, status as "status: JobRunStatus", error, started, finished
+            FROM job_runs
+            WHERE $1::varchar IS NULL OR job_name = $1::varchar
+            ORDER BY started DESC
+            LIMIT $2
+            "#,
+            job_name,
+            limit
+        )
+        .fetch_all(&self.client)
+        .await?)
+    }
+
+    /// Deletes the runs that started longer ago than the retention period
+    ///
+    /// Returns the number of runs deleted.
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_runs(&self, retention_period: Duration) -> AppResult<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM job_runs
+            WHERE started < now() - make_interval(secs => $1)
+            "#,
+            retention_period.as_secs_f64()
+        )
+        .execute(&self.client)
+        .await?
+        .rows_affected())
+    }
+}
+
+impl JobLock {
+    /// Releases the lock so that the job may run again
+    pub async fn unlock(mut self) -> AppResult<()> {
+        if let Some(connection) = self.connection.as_mut() {
+            sqlx::query!(
+                "SELECT pg_advisory_unlock(hashtextextended($1, 0))",
+                self.job_name
+            )
+            .fetch_one(connection)
+            .await?;
+        }
+
+        // Released, so the connection can go back to the pool
+        self.connection.take();
+        Ok(())
+    }
+}
+
+impl Drop for JobLock {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            tracing::warn!(
+                "Closing the connection of unreleased lock {}",
+                self.job_name
+            );
+            drop(connection.detach());
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
     models::users::{NewUser, User, UserFilter, UserUpdate},
+    services::auth::Lockout,
 };
 
 #[derive(Clone, Debug)]
@@ -16,6 +19,7 @@ impl UserRepo {
         Self { client }
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn insert(&self, new_user: NewUser) -> AppResult<User> {
         // Verify that the NewUser password is hashed
         if !new_user.password.starts_with("$argon2") {
@@ -37,6 +41,7 @@ impl UserRepo {
         Ok(user)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get(&self, id: Uuid) -> AppResult<User> {
         let user = sqlx::query_as!(
             User,
@@ -52,6 +57,7 @@ impl UserRepo {
         Ok(user)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_by_email(&self, email: String) -> AppResult<Option<User>> {
         let user = sqlx::query_as!(
             User,
@@ -67,6 +73,7 @@ impl UserRepo {
         Ok(user)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn list(&self, filter: UserFilter) -> AppResult<Vec<User>> {
         let query = sqlx::query_as!(
             User,
@@ -84,6 +91,7 @@ impl UserRepo {
         Ok(users)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn update(&self, user_id: Uuid, update: UserUpdate) -> AppResult<User> {
         let query = sqlx::query_as!(
             User,
@@ -103,6 +111,7 @@ impl UserRepo {
         Ok(update)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn delete(&self, id: Uuid) -> AppResult<()> {
         sqlx::query!(
             r#"
@@ -115,4 +124,106 @@ impl UserRepo {
         .await?;
         Ok(())
     }
+
+    /// Whether the account is locked after too many failed logins
+    #[tracing::instrument(skip_all)]
+    pub async fn is_locked(&self, id: Uuid) -> AppResult<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT coalesce(locked_until > now(), false) as "locked!"
+            FROM users
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&self.client)
+        .await?)
+    }
+
+    /// Counts a failed login, locking the account when the lockout says so
+    ///
+    /// Returns how long the account was locked for, if it was.
+    #[tracing::instrument(skip_all)]
+    pub async fn record_failed_login(
+        &self,
+        id: Uuid,
+        lockout: &Lockout,
+    ) -> AppResult<Option<Duration>> {
+        let failures = sqlx::query_scalar!(
+            r#"
+            UPDATE users
+            SET failed_logins = failed_logins + 1
+            WHERE id = $1
+            RETURNING failed_logins
+            "#,
+            id
+        )
+        .fetch_one(&self.client)
+        .await?;
+
+        let lock = lockout.lock_for(failures.max(0) as u32);
+        if let Some(lock) = lock {
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET locked_until = now() + make_interval(secs => $2)
+                WHERE id = $1
+                "#,
+                id,
+                lock.as_secs_f64()
+            )
+            .execute(&self.client)
+            .await?;
+        }
+
+        Ok(lock)
+    }
+
+    /// Forgets the failed logins of the account and unlocks it
+    #[tracing::instrument(skip_all)]
+    pub async fn reset_failed_logins(&self, id: Uuid) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET failed_logins = 0, locked_until = NULL
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the user has proved they own the email of the account
+    #[tracing::instrument(skip_all)]
+    pub async fn is_email_verified(&self, id: Uuid) -> AppResult<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT email_verified IS NOT NULL as "verified!"
+            FROM users
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&self.client)
+        .await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_email_verified(&self, id: Uuid) -> AppResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET email_verified = coalesce(email_verified, now())
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
 }
@@ -1,3 +1,11 @@
+pub mod accounts;
 pub mod auth;
 pub mod game;
+pub mod mailer;
+pub mod matching;
+pub mod oidc;
+pub mod permissions;
+pub mod presence;
+pub mod rate_limit;
 pub mod session;
+pub mod tokens;
@@ -0,0 +1,119 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    error::AppResult,
+    models::users::User,
+    services::{
+        mailer::{Email, Mailer},
+        tokens::{TokenPurpose, TokenSigner},
+    },
+};
+
+/// Emails users the links to verify their email and reset their password
+#[derive(Clone, Debug)]
+pub struct AccountEmails {
+    mailer: Arc<dyn Mailer>,
+    signer: TokenSigner,
+    /// The address the app is reached at, which the links point to
+    public_url: String,
+    verification_ttl: Duration,
+    reset_ttl: Duration,
+}
+
+impl AccountEmails {
+    pub fn new(
+        mailer: Arc<dyn Mailer>,
+        signer: TokenSigner,
+        public_url: &str,
+        verification_ttl: Duration,
+        reset_ttl: Duration,
+    ) -> Self {
+        Self {
+            mailer,
+            signer,
+            public_url: public_url.trim_end_matches('/').to_owned(),
+            verification_ttl,
+            reset_ttl,
+        }
+    }
+
+    pub async fn send_verification(&self, user: &User) -> AppResult<()> {
+        let token = self
+            .signer
+            .sign(TokenPurpose::VerifyEmail, user, self.verification_ttl);
+        let link = format!("{}/verify-email?token={}", self.public_url, token);
+
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Verify your email for NameThat".into(),
+                body: format!(
+                    "Welcome to NameThat!\n\n\
+                    Open this link to verify your email, it works for {}:\n\n{}\n\n\
+                    If you did not create an account, you can ignore this email.",
+                    describe(self.verification_ttl),
+                    link
+                ),
+            })
+            .await
+    }
+
+    pub async fn send_password_reset(&self, user: &User) -> AppResult<()> {
+        let token = self
+            .signer
+            .sign(TokenPurpose::ResetPassword, user, self.reset_ttl);
+        let link = format!("{}/reset-password?token={}", self.public_url, token);
+
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Reset your NameThat password".into(),
+                body: format!(
+                    "Someone asked to reset the password of your NameThat account.\n\n\
+                    Open this link to choose a new one, it works for {}:\n\n{}\n\n\
+                    If it was not you, you can ignore this email.",
+                    describe(self.reset_ttl),
+                    link
+                ),
+            })
+            .await
+    }
+
+    /// Tells the owner of an account that someone tried to register their
+    /// email, which is all a registration with it does
+    pub async fn send_account_exists(&self, user: &User) -> AppResult<()> {
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "You already have a NameThat account".into(),
+                body: format!(
+                    "Someone tried to create a NameThat account with this email, \
+                    but you already have one, so nothing was changed.\n\n\
+                    If it was you, log in at {0}/login, or reset your password at \
+                    {0}/forgot-password if you forgot it.\n\n\
+                    If it was not you, you can ignore this email.",
+                    self.public_url
+                ),
+            })
+            .await
+    }
+
+    /// Checks a token from one of the links, see [`TokenSigner::verify`]
+    pub fn verify(&self, token: &str, purpose: TokenPurpose, user: &User) -> AppResult<()> {
+        self.signer.verify(token, purpose, user)
+    }
+}
+
+/// The time a link works for, in the largest whole unit
+fn describe(ttl: Duration) -> String {
+    let (amount, unit) = match ttl.as_secs() {
+        s if s >= 24 * 60 * 60 => (s / (24 * 60 * 60), "day"),
+        s if s >= 60 * 60 => (s / (60 * 60), "hour"),
+        s => ((s / 60).max(1), "minute"),
+    };
+
+    match amount {
+        1 => format!("1 {}", unit),
+        _ => format!("{} {}s", amount, unit),
+    }
+}
@@ -1,7 +1,12 @@
+use std::{sync::OnceLock, time::Duration};
+
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use rand::rngs::OsRng;
 
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+
+/// Longest email the users table holds
+const MAX_EMAIL_LENGTH: usize = 100;
 
 pub struct AuthService;
 
@@ -19,4 +24,90 @@ impl AuthService {
             .verify_password(password.as_bytes(), &parsed_hash)
             .is_ok())
     }
+
+    /// Checks that an email for a new account is an address mail can be sent
+    /// to
+    pub fn validate_email(email: &str) -> AppResult<()> {
+        if email.len() > MAX_EMAIL_LENGTH || email.parse::<lettre::Address>().is_err() {
+            return Err(AppError::ValidationError(
+                "Enter a valid email address".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the password against a hash no account has, so that logging in
+    /// to an email without an account takes as long as with a wrong password
+    pub fn check_without_account(password: &str) -> AppResult<()> {
+        static HASH: OnceLock<String> = OnceLock::new();
+        let hash = match HASH.get() {
+            Some(hash) => hash,
+            None => {
+                let hash = Self::hash_password("no account has this password")?;
+                HASH.get_or_init(|| hash)
+            }
+        };
+
+        Self::check_password(password, hash)?;
+        Ok(())
+    }
+}
+
+/// How accounts are locked after repeated failed logins
+///
+/// Once an account reaches the failure limit it is locked for the base
+/// period, and every failure after that doubles the lock, up to the longest
+/// lock. A successful login starts over.
+#[derive(Clone, Copy, Debug)]
+pub struct Lockout {
+    pub max_failures: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Lockout {
+    /// How long to lock the account for after the given number of failures
+    /// in a row, if at all
+    pub fn lock_for(&self, failures: u32) -> Option<Duration> {
+        let doublings = failures.checked_sub(self.max_failures)?;
+        let lock = self
+            .base
+            .checked_mul(2u32.saturating_pow(doublings))
+            .unwrap_or(self.max);
+
+        Some(lock.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emails_must_be_addresses() {
+        assert!(AuthService::validate_email("player@example.com").is_ok());
+        assert!(AuthService::validate_email("player").is_err());
+        assert!(AuthService::validate_email("player@").is_err());
+        assert!(AuthService::validate_email("two words@example.com").is_err());
+        assert!(AuthService::validate_email(&format!("{}@example.com", "a".repeat(100))).is_err());
+    }
+
+    #[test]
+    fn locks_double_after_the_limit() {
+        let lockout = Lockout {
+            max_failures: 3,
+            base: Duration::from_secs(60),
+            max: Duration::from_secs(60 * 60),
+        };
+
+        assert_eq!(lockout.lock_for(2), None);
+        assert_eq!(lockout.lock_for(3), Some(Duration::from_secs(60)));
+        assert_eq!(lockout.lock_for(4), Some(Duration::from_secs(120)));
+        assert_eq!(lockout.lock_for(5), Some(Duration::from_secs(240)));
+        assert_eq!(lockout.lock_for(9), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(
+            lockout.lock_for(u32::MAX),
+            Some(Duration::from_secs(60 * 60))
+        );
+    }
 }
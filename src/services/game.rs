@@ -1,62 +1,121 @@
-use axum::extract::ws::{Message, WebSocket};
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use futures_util::{stream::SplitSink, SinkExt};
+use rand::seq::SliceRandom;
 use tokio::sync::broadcast::Sender;
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
     models::games::{
-        Game, GameAction, GameBroadcast, GameMessage, NewAnswer, NewRound, PlayerType,
+        Answer, AnswerKey, Game, GameAction, GameBroadcast, GameMessage, GameMode, GameRole,
+        GameStatus, NewAnswer, NewRound, PlayerType, Round,
     },
     repositories::games::GameRepo,
+    services::{matching::AnswerMatcher, permissions},
 };
 
 #[derive(Clone)]
 pub struct GameActionService {
     game_id: Uuid,
     game_repo: GameRepo,
+    /// The logged in user, if any
+    user_id: Option<Uuid>,
     user_type: PlayerType,
+    role: GameRole,
 }
 
 impl GameActionService {
-    pub fn new(game_id: Uuid, game_repo: GameRepo, user_type: PlayerType) -> Self {
+    pub fn new(
+        game_id: Uuid,
+        game_repo: GameRepo,
+        user_id: Option<Uuid>,
+        user_type: PlayerType,
+        role: GameRole,
+    ) -> Self {
         Self {
             game_id,
             game_repo,
+            user_id,
             user_type,
+            role,
         }
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn handle_action(&self, message: &GameAction) -> AppResult<()> {
+        let role = self.current_role().await?;
+        if !permissions::is_allowed(&role, message) {
+            return Err(AppError::AuthorizationError(
+                "You are not allowed to do that in this game".into(),
+            ));
+        }
+        if !permissions::is_allowed_while_paused(message) && self.get_game().await?.paused {
+            return Err(AppError::ValidationError("The game is paused".into()));
+        }
+
         match message {
             GameAction::PlayerJoin { .. } => (),
-            GameAction::StartRound { round } => self.start_round(round.to_owned()).await?,
-            GameAction::UserAnswer { round_id, answer } => {
-                self.add_user_answer(round_id, answer).await?
+            GameAction::StartRound { round, force } => {
+                self.start_round(round.to_owned(), *force).await?
             }
+            GameAction::SetReady { ready } => self.set_ready(*ready).await?,
+            GameAction::Pause => self.pause().await?,
+            GameAction::Resume => self.resume().await?,
+            GameAction::UserAnswer {
+                round_id,
+                answer,
+                answer_id,
+            } => {
+                self.add_user_answer(round_id, answer_id.as_ref(), answer)
+                    .await?
+            }
+            GameAction::WithdrawAnswer {
+                round_id,
+                answer_id,
+            } => self.withdraw_answer(round_id, answer_id.as_ref()).await?,
             GameAction::CloseAnswers { round_id } => self.close_answers(round_id).await?,
             GameAction::RevealAnswer { answer_id } => self.reveal_answer(answer_id).await?,
+            GameAction::RevealAll { round_id } => self.reveal_all(round_id).await?,
             GameAction::LikeAnswer { answer_id } => self.like_answer(answer_id).await?,
-            GameAction::EndRound { round_id, winner } => {
-                self.end_round(round_id, &winner.parse()?).await?
+            GameAction::EndRound {
+                round_id,
+                winner,
+                answer_id,
+            } => {
+                let winner = winner.as_deref().map(str::parse).transpose()?;
+                self.end_round(round_id, winner.as_ref(), answer_id.as_ref())
+                    .await?
             }
             GameAction::EndGame => self.end_game().await?,
+            GameAction::RequestPromotion => self.request_promotion().await?,
+            GameAction::PromotePlayer { player_id } => self.promote_player(player_id).await?,
+            GameAction::RemoveAnswer { answer_id } => self.remove_answer(answer_id).await?,
         }
 
         Ok(())
     }
 
-    pub async fn start_round(&self, round: i32) -> AppResult<()> {
-        let game = self.get_game().await?;
-
-        if self.user_type != PlayerType::GameMaster {
-            return Err(AppError::AuthorizationError(
-                "User cannot modify the game".into(),
-            ));
+    /// Starts the given round, starting the game with the first round.
+    ///
+    /// The game only starts once the minimum number of players are ready,
+    /// unless the game master forces it, and the round is valid.
+    #[tracing::instrument(skip_all)]
+    pub async fn start_round(&self, round: i32, force: bool) -> AppResult<()> {
+        if round < 1 {
+            return Err(AppError::ValidationError("Invalid round number".into()));
         }
+        let game = self.get_game().await?;
 
         if round == 1 {
-            self.game_repo.start(&game.id).await?;
+            let missing = game.min_players - game.ready_player_count();
+            if !force && missing > 0 {
+                return Err(AppError::ValidationError(format!(
+                    "Waiting for {} more player(s) to be ready",
+                    missing
+                )));
+            }
         }
 
         let image_url = game
@@ -64,55 +123,125 @@ impl GameActionService {
             .get(round as usize - 1)
             .ok_or(AppError::ValidationError("Invalid round number".into()))?
             .clone();
-
-        self.game_repo
-            .add_round(NewRound {
-                game_id: game.id,
-                round_number: round,
-                image_url,
-            })
-            .await?;
+        let prompt = game
+            .prompts
+            .get(round as usize - 1)
+            .filter(|p| !p.is_empty())
+            .cloned();
+        let answer_key = game.answer_key(round - 1);
+        Self::validate_round_mode(&game.mode, prompt.as_deref(), answer_key)?;
+
+        let new_round = NewRound {
+            game_id: game.id,
+            round_number: round,
+            image_url,
+            prompt,
+            mode: game.mode.clone(),
+        };
+        match round {
+            1 => self.game_repo.start(new_round).await?,
+            _ => self.game_repo.add_round(new_round).await?,
+        };
 
         Ok(())
     }
 
-    pub async fn add_user_answer(&self, round_id: &Uuid, answer: &str) -> AppResult<()> {
-        let player_id = match self.user_type {
-            PlayerType::Player { id, .. } => id,
-            _ => return Err(AppError::AuthorizationError("User cannot answer".into())),
-        };
-
-        let game = self.game_repo.get_by_round_id(&round_id).await?;
-        if self.game_id != game.id {
-            return Err(AppError::ValidationError(
-                "Invalid game id for round".into(),
-            ));
+    /// Adds an answer for the player, or edits one of their answers when an
+    /// answer id is given.
+    ///
+    /// Players may give up to `max_answers_per_round` answers. When only one
+    /// answer is allowed, answering again replaces the previous answer. In
+    /// guess the answer rounds the answer is checked against the answer key and
+    /// the player scores a point while any of their answers is correct.
+    #[tracing::instrument(skip_all)]
+    pub async fn add_user_answer(
+        &self,
+        round_id: &Uuid,
+        answer_id: Option<&Uuid>,
+        answer: &str,
+    ) -> AppResult<()> {
+        let (game, round) = self.get_open_round(round_id).await?;
+        let player_id = self.get_answering_player_id(&game, &round)?;
+        let player_answers: Vec<&Answer> = round
+            .answers
+            .iter()
+            .filter(|a| a.player_id == Some(player_id))
+            .collect();
+        let correct = Self::check_answer(&game, &round, answer);
+
+        match answer_id {
+            Some(answer_id) => {
+                if !player_answers.iter().any(|a| a.id == *answer_id) {
+                    return Err(AppError::ValidationError(
+                        "Answer does not belong to the player".into(),
+                    ));
+                }
+                self.game_repo
+                    .update_answer(answer_id, answer, correct)
+                    .await?;
+            }
+            None if player_answers.len() < game.max_answers_per_round as usize => {
+                self.game_repo
+                    .add_answer(
+                        NewAnswer {
+                            player_id,
+                            round_id: round_id.to_owned(),
+                            value: answer.to_owned(),
+                            correct,
+                        },
+                        game.max_answers_per_round,
+                    )
+                    .await?;
+            }
+            None if game.max_answers_per_round == 1 => {
+                self.game_repo
+                    .update_answer(&player_answers[0].id, answer, correct)
+                    .await?;
+            }
+            None => {
+                return Err(AppError::ValidationError(format!(
+                    "Only {} answers are allowed per round",
+                    game.max_answers_per_round
+                )));
+            }
         }
 
-        let round =
-            game.rounds
-                .iter()
-                .find(|r| r.id == *round_id)
-                .ok_or(AppError::ValidationError(
-                    "Invalid round id for game".into(),
-                ))?;
-        if round.answers_closed {
-            return Err(AppError::ValidationError(
-                "Answers are closed for this round".into(),
-            ));
-        }
+        self.rescore_player(&round, &player_id).await
+    }
 
-        self.game_repo
-            .add_answer(NewAnswer {
-                player_id,
-                round_id: round_id.to_owned(),
-                value: answer.to_owned(),
-            })
-            .await?;
+    #[tracing::instrument(skip_all)]
+    pub async fn withdraw_answer(
+        &self,
+        round_id: &Uuid,
+        answer_id: Option<&Uuid>,
+    ) -> AppResult<()> {
+        let (game, round) = self.get_open_round(round_id).await?;
+        let player_id = self.get_answering_player_id(&game, &round)?;
+
+        match answer_id {
+            Some(answer_id) => {
+                if !round
+                    .answers
+                    .iter()
+                    .any(|a| a.id == *answer_id && a.player_id == Some(player_id))
+                {
+                    return Err(AppError::ValidationError(
+                        "Answer does not belong to the player".into(),
+                    ));
+                }
+                self.game_repo.remove_answer(answer_id).await?;
+            }
+            None => {
+                self.game_repo
+                    .remove_player_answers(round_id, &player_id)
+                    .await?;
+            }
+        }
 
-        Ok(())
+        self.rescore_player(&round, &player_id).await
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn close_answers(&self, round_id: &Uuid) -> AppResult<()> {
         let game = self.game_repo.get_by_round_id(&round_id).await?;
         if self.game_id != game.id {
@@ -121,34 +250,55 @@ impl GameActionService {
             ));
         }
 
-        if self.user_type != PlayerType::GameMaster {
-            return Err(AppError::AuthorizationError(
-                "User cannot modify the game".into(),
+        if game
+            .rounds
+            .iter()
+            .any(|r| r.id == *round_id && r.answers_closed)
+        {
+            return Err(AppError::ValidationError(
+                "Answers are already closed for this round".into(),
             ));
         }
 
-        self.game_repo.close_answers(&round_id).await?;
+        let game = self.close_round_answers(round_id).await?;
+        if game.auto_reveal {
+            self.start_reveal_sequence(round_id).await?;
+        }
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn reveal_answer(&self, answer_id: &Uuid) -> AppResult<()> {
         let game = self.game_repo.get_by_answer_id(&answer_id).await?;
         if self.game_id != game.id {
             return Err(AppError::ValidationError("Invalid answer id".into()));
         }
 
-        if self.user_type != PlayerType::GameMaster {
-            return Err(AppError::AuthorizationError(
-                "User cannot modify the game".into(),
-            ));
-        }
-
         self.game_repo.show_answer(&answer_id).await?;
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
+    pub async fn reveal_all(&self, round_id: &Uuid) -> AppResult<()> {
+        let game = self.game_repo.get_by_round_id(round_id).await?;
+        if self.game_id != game.id {
+            return Err(AppError::ValidationError("Invalid round id".into()));
+        }
+
+        let answers_closed = game
+            .rounds
+            .iter()
+            .any(|r| r.id == *round_id && r.answers_closed);
+        if !answers_closed {
+            self.close_round_answers(round_id).await?;
+        }
+
+        self.start_reveal_sequence(round_id).await
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn like_answer(&self, answer_id: &Uuid) -> AppResult<()> {
         let game = self.game_repo.get_by_answer_id(&answer_id).await?;
         if self.game_id != game.id {
@@ -160,41 +310,454 @@ impl GameActionService {
         Ok(())
     }
 
-    pub async fn end_round(&self, round_id: &Uuid, winner: &Uuid) -> AppResult<()> {
+    /// Ends the round with the winner selected by the game master.
+    ///
+    /// Guess the answer rounds are scored as answers come in, so the winner is
+    /// optional there and defaults to the player of the first correct answer.
+    /// Other rounds need a winner, who scores a point.
+    #[tracing::instrument(skip_all)]
+    pub async fn end_round(
+        &self,
+        round_id: &Uuid,
+        winner: Option<&Uuid>,
+        answer_id: Option<&Uuid>,
+    ) -> AppResult<()> {
         let game = self.game_repo.get_by_round_id(&round_id).await?;
         if self.game_id != game.id {
             return Err(AppError::ValidationError("Invalid round id".into()));
         }
 
-        if self.user_type != PlayerType::GameMaster {
-            return Err(AppError::AuthorizationError(
-                "User cannot modify the game".into(),
-            ));
+        let round =
+            game.rounds
+                .iter()
+                .find(|r| r.id == *round_id)
+                .ok_or(AppError::ValidationError(
+                    "Invalid round id for game".into(),
+                ))?;
+        let auto_scored = round.mode == GameMode::GuessTheAnswer;
+
+        let (winner, answer_id) = match (winner, auto_scored) {
+            (Some(winner), _) => (Some(*winner), answer_id.copied()),
+            (None, true) => {
+                let first_correct = round.answers.iter().find(|a| a.correct == Some(true));
+                (
+                    first_correct.and_then(|a| a.player_id),
+                    first_correct.map(|a| a.id),
+                )
+            }
+            (None, false) => {
+                return Err(AppError::ValidationError(
+                    "A winner must be selected for this round".into(),
+                ))
+            }
+        };
+
+        if let (Some(winner), Some(answer_id)) = (winner, answer_id) {
+            let is_winning_answer = round
+                .answers
+                .iter()
+                .any(|a| a.id == answer_id && a.player_id == Some(winner));
+            if !is_winning_answer {
+                return Err(AppError::ValidationError(
+                    "Answer was not given by the winner in this round".into(),
+                ));
+            }
         }
 
-        self.game_repo.end_round(round_id, winner).await?;
-        self.game_repo.increment_score(winner).await?;
+        self.game_repo
+            .end_round(round_id, winner.as_ref(), answer_id.as_ref())
+            .await?;
+        if let (Some(winner), false) = (winner, auto_scored) {
+            self.game_repo.increment_score(round_id, &winner).await?;
+        }
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn end_game(&self) -> AppResult<()> {
         let game = self.get_game().await?;
 
-        if self.user_type != PlayerType::GameMaster {
-            return Err(AppError::AuthorizationError(
-                "User cannot modify the game".into(),
+        self.game_repo.end(&game.id).await?;
+
+        Ok(())
+    }
+
+    /// Pauses the game, suspending any answers being revealed automatically
+    #[tracing::instrument(skip_all)]
+    pub async fn pause(&self) -> AppResult<()> {
+        let game = self.get_game().await?;
+        if game.status != GameStatus::Started {
+            return Err(AppError::ValidationError(
+                "Only a running game can be paused".into(),
+            ));
+        }
+        if game.paused {
+            return Err(AppError::ValidationError(
+                "The game is already paused".into(),
             ));
         }
 
-        self.game_repo.end(&game.id).await?;
+        self.game_repo.set_paused(&game.id, true).await?;
+        Ok(())
+    }
+
+    /// Resumes the game at the round it was paused in, continuing to reveal
+    /// the answers if that was suspended by the pause.
+    #[tracing::instrument(skip_all)]
+    pub async fn resume(&self) -> AppResult<()> {
+        let game = self.get_game().await?;
+        if !game.paused {
+            return Err(AppError::ValidationError("The game is not paused".into()));
+        }
+
+        let game = self.game_repo.set_paused(&game.id, false).await?;
+        if let Some(round) = game.rounds.last() {
+            if round.reveal_suspended && !round.ended {
+                self.start_reveal_sequence(&round.id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the player as ready, or no longer ready, for the game to start
+    #[tracing::instrument(skip_all)]
+    pub async fn set_ready(&self, ready: bool) -> AppResult<()> {
+        let player_id = match self.user_type {
+            PlayerType::Player { id, .. } => id,
+            _ => {
+                return Err(AppError::ValidationError(
+                    "Only players can get ready".into(),
+                ))
+            }
+        };
+
+        let game = self.get_game().await?;
+        if game.status != GameStatus::Pending {
+            return Err(AppError::ValidationError(
+                "The game has already started".into(),
+            ));
+        }
+
+        self.game_repo.set_ready(&player_id, ready).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn request_promotion(&self) -> AppResult<()> {
+        let player_id = match self.user_type {
+            PlayerType::Player { id, .. } | PlayerType::Observer { id, .. } => id,
+            PlayerType::GameMaster | PlayerType::Moderator => {
+                return Err(AppError::ValidationError(
+                    "Only observers can ask to play".into(),
+                ))
+            }
+        };
+
+        let game = self.get_game().await?;
+        if game.status == GameStatus::Finished {
+            return Err(AppError::ValidationError("The game has finished".into()));
+        }
+        match game.players.iter().find(|p| p.id == player_id) {
+            Some(player) if player.is_observer => (),
+            Some(_) => {
+                return Err(AppError::ValidationError(
+                    "Player is already playing".into(),
+                ))
+            }
+            None => {
+                return Err(AppError::ValidationError(
+                    "Player is not part of this game".into(),
+                ))
+            }
+        }
+
+        self.game_repo.request_promotion(&player_id).await?;
+
+        Ok(())
+    }
+
+    /// Promotes an observer to a player, on the game's late join terms.
+    #[tracing::instrument(skip_all)]
+    pub async fn promote_player(&self, player_id: &Uuid) -> AppResult<()> {
+        let game = self.get_game().await?;
+
+        if !game
+            .players
+            .iter()
+            .any(|p| p.id == *player_id && p.is_observer)
+        {
+            return Err(AppError::ValidationError(
+                "Player is not observing this game".into(),
+            ));
+        }
+
+        let (first_round, score) = game.late_join_terms();
+        self.game_repo
+            .promote_player(player_id, first_round, score)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes an inappropriate answer, taking back the point it scored in
+    /// guess the answer rounds.
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_answer(&self, answer_id: &Uuid) -> AppResult<()> {
+        let game = self.game_repo.get_by_answer_id(answer_id).await?;
+        if self.game_id != game.id {
+            return Err(AppError::ValidationError("Invalid answer id".into()));
+        }
+
+        let round = game
+            .rounds
+            .iter()
+            .find(|r| r.answers.iter().any(|a| a.id == *answer_id))
+            .cloned()
+            .ok_or(AppError::NotFoundError("Answer not found".into()))?;
+        let player_id = round
+            .answers
+            .iter()
+            .find(|a| a.id == *answer_id)
+            .and_then(|a| a.player_id);
+
+        self.game_repo.remove_answer(answer_id).await?;
+        if let Some(player_id) = player_id {
+            self.rescore_player(&round, &player_id).await?;
+        }
 
         Ok(())
     }
 
+    /// Validates that a round with the given prompt and answer key can be
+    /// played in the mode
+    pub fn validate_round_mode(
+        mode: &GameMode,
+        prompt: Option<&str>,
+        answer_key: Option<&AnswerKey>,
+    ) -> AppResult<()> {
+        match (mode, prompt) {
+            (GameMode::FillInTheBlank, Some(prompt)) if prompt.contains(GameMode::BLANK) => Ok(()),
+            (GameMode::FillInTheBlank, _) => Err(AppError::ValidationError(format!(
+                "Fill in the blank rounds need a prompt containing {}",
+                GameMode::BLANK
+            ))),
+            (GameMode::GuessTheAnswer, _) => match answer_key {
+                Some(key) if !key.expected_answer.trim().is_empty() => Ok(()),
+                _ => Err(AppError::ValidationError(
+                    "Guess the answer rounds need an expected answer".into(),
+                )),
+            },
+            (GameMode::Caption | GameMode::GuessTitle, _) => Ok(()),
+        }
+    }
+
     async fn get_game(&self) -> AppResult<Game> {
         Ok(self.game_repo.get(&self.game_id).await?)
     }
+
+    /// Closes the answers for the round and shuffles the order in which they
+    /// will be revealed.
+    async fn close_round_answers(&self, round_id: &Uuid) -> AppResult<Game> {
+        let game = self.game_repo.close_answers(round_id).await?;
+        let mut reveal_order: Vec<Uuid> = game
+            .rounds
+            .iter()
+            .filter(|r| r.id == *round_id)
+            .flat_map(|r| r.answers.iter().map(|a| a.id))
+            .collect();
+        reveal_order.shuffle(&mut rand::thread_rng());
+
+        self.game_repo
+            .set_reveal_order(round_id, &reveal_order)
+            .await
+    }
+
+    /// Starts revealing the remaining answers of the round in the background,
+    /// unless they are already being revealed.
+    ///
+    /// The answers are revealed by the reveal job, so that the sequence
+    /// carries on should this server go away.
+    async fn start_reveal_sequence(&self, round_id: &Uuid) -> AppResult<()> {
+        self.game_repo.start_revealing(round_id).await?;
+        Ok(())
+    }
+
+    /// Checks the answer against the answer key of a guess the answer round.
+    ///
+    /// Returns `None` for rounds that are not scored automatically.
+    fn check_answer(game: &Game, round: &Round, answer: &str) -> Option<bool> {
+        if round.mode != GameMode::GuessTheAnswer {
+            return None;
+        }
+
+        let matcher = AnswerMatcher::new(game.answer_tolerance.max(0) as usize);
+        let is_match = game
+            .answer_key(round.round_number - 1)
+            .map(|key| matcher.is_match(answer, key))
+            .unwrap_or(false);
+        Some(is_match)
+    }
+
+    /// Updates the score of the player after their answers to a guess the
+    /// answer round have changed.
+    ///
+    /// The player holds one point for the round while any of their answers is
+    /// correct, so the score only changes when that flips.
+    async fn rescore_player(&self, previous_round: &Round, player_id: &Uuid) -> AppResult<()> {
+        if previous_round.mode != GameMode::GuessTheAnswer {
+            return Ok(());
+        }
+
+        let has_correct_answer = |round: &Round| {
+            round
+                .answers
+                .iter()
+                .any(|a| a.player_id == Some(*player_id) && a.correct == Some(true))
+        };
+        let was_correct = has_correct_answer(previous_round);
+        let game = self.game_repo.get_by_round_id(&previous_round.id).await?;
+        let is_correct = game
+            .rounds
+            .iter()
+            .find(|r| r.id == previous_round.id)
+            .map(has_correct_answer)
+            .unwrap_or(false);
+
+        match (was_correct, is_correct) {
+            (false, true) => {
+                self.game_repo
+                    .increment_score(&previous_round.id, player_id)
+                    .await?;
+            }
+            (true, false) => {
+                self.game_repo
+                    .decrement_score(&previous_round.id, player_id)
+                    .await?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Gets the current role of the user in the game
+    ///
+    /// Observers may be promoted and members may have their role changed or
+    /// be removed while connected, so every role but the owner's is looked up
+    /// rather than taken from when they joined.
+    async fn current_role(&self) -> AppResult<GameRole> {
+        match (self.role, &self.user_type) {
+            (GameRole::Owner, _) => Ok(GameRole::Owner),
+            (
+                GameRole::Player | GameRole::Observer,
+                PlayerType::Player { id, .. } | PlayerType::Observer { id, .. },
+            ) => match self.game_repo.get_player(id).await?.is_observer {
+                true => Ok(GameRole::Observer),
+                false => Ok(GameRole::Player),
+            },
+            _ => {
+                let member_role = match self.user_id {
+                    Some(user_id) => {
+                        self.game_repo
+                            .get_member_role(&self.game_id, &user_id)
+                            .await?
+                    }
+                    None => None,
+                };
+                member_role.ok_or(AppError::AuthorizationError(
+                    "You no longer help run this game".into(),
+                ))
+            }
+        }
+    }
+
+    /// Gets the id of the user if they may answer in the round
+    fn get_answering_player_id(&self, game: &Game, round: &Round) -> AppResult<Uuid> {
+        let player_id = match self.user_type {
+            PlayerType::Player { id, .. } | PlayerType::Observer { id, .. } => id,
+            PlayerType::GameMaster | PlayerType::Moderator => {
+                return Err(AppError::AuthorizationError("User cannot answer".into()))
+            }
+        };
+
+        let player = game
+            .players
+            .iter()
+            .find(|p| p.id == player_id && !p.is_observer)
+            .ok_or(AppError::AuthorizationError("User cannot answer".into()))?;
+        if let Some(first_round) = player.first_round {
+            if round.round_number < first_round {
+                return Err(AppError::ValidationError(format!(
+                    "You can answer from round {}",
+                    first_round
+                )));
+            }
+        }
+
+        Ok(player_id)
+    }
+
+    /// Gets the game and round if the round belongs to this game and is still
+    /// accepting answers.
+    async fn get_open_round(&self, round_id: &Uuid) -> AppResult<(Game, Round)> {
+        let game = self.game_repo.get_by_round_id(round_id).await?;
+        if self.game_id != game.id {
+            return Err(AppError::ValidationError(
+                "Invalid game id for round".into(),
+            ));
+        }
+
+        let round = game
+            .rounds
+            .iter()
+            .find(|r| r.id == *round_id)
+            .cloned()
+            .ok_or(AppError::ValidationError(
+                "Invalid round id for game".into(),
+            ))?;
+        if round.answers_closed {
+            return Err(AppError::ValidationError(
+                "Answers are closed for this round".into(),
+            ));
+        }
+
+        Ok((game, round))
+    }
+}
+
+/// Reveals the next answer of a round being revealed automatically, in the
+/// reveal order, which the reveal job does every reveal interval.
+///
+/// Revealing stops once every answer is shown or the game master ends the
+/// round, and is suspended if the game is paused so that it can continue on
+/// resume.
+pub async fn reveal_next_answer(
+    game_repo: &GameRepo,
+    broadcast_service: &GameBroadcastService,
+    round_id: &Uuid,
+) -> AppResult<()> {
+    let game = game_repo.get_by_round_id(round_id).await?;
+    let round = game
+        .rounds
+        .iter()
+        .find(|r| r.id == *round_id)
+        .ok_or(AppError::NotFoundError("Round not found".into()))?;
+    let unrevealed: Vec<&Answer> = round.answers.iter().filter(|a| !a.shown).collect();
+
+    if game.paused && !round.ended {
+        game_repo.suspend_revealing(round_id).await?;
+    } else if let (Some(next), false) = (unrevealed.first(), round.ended) {
+        game_repo.show_answer(&next.id).await?;
+        if unrevealed.len() == 1 {
+            game_repo.stop_revealing(round_id).await?;
+        }
+    } else {
+        game_repo.stop_revealing(round_id).await?;
+    }
+
+    broadcast_service.broadcast_game_state().await
 }
 
 pub struct GameMessageService {
@@ -212,11 +775,8 @@ impl GameMessageService {
         }
     }
 
-    pub async fn send_text(&mut self, text: &str) -> AppResult<()> {
-        self.sender
-            .send(Message::Text(text.to_owned()))
-            .await
-            .map_err(|e| AppError::InternalError(e.to_string()))
+    pub fn game_id(&self) -> &Uuid {
+        &self.game_id
     }
 
     pub async fn send(&mut self, message: GameMessage) -> AppResult<()> {
@@ -227,6 +787,63 @@ impl GameMessageService {
             .map_err(|e| AppError::InternalError(e.to_string()))
     }
 
+    pub async fn ping(&mut self) -> AppResult<()> {
+        self.sender
+            .send(Message::Ping(vec![]))
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))
+    }
+
+    /// Tells the client to reconnect after a while and closes the socket,
+    /// used when the server is shutting down.
+    pub async fn restart(&mut self, retry_after: Duration) -> AppResult<()> {
+        self.send(GameMessage::Reconnect {
+            retry_after_seconds: retry_after.as_secs(),
+        })
+        .await?;
+        self.sender
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::RESTART,
+                reason: "Server restarting".into(),
+            })))
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))
+    }
+
+    /// Tells the client its messages are being dropped until it waits
+    pub async fn rate_limited(&mut self, retry_after: Duration) -> AppResult<()> {
+        self.send(GameMessage::RateLimited {
+            retry_after_ms: retry_after.as_millis() as u64,
+        })
+        .await
+    }
+
+    /// Tells a member removed from the game why and closes their socket
+    pub async fn member_removed(&mut self) -> AppResult<()> {
+        self.send(GameMessage::Notification {
+            message: "You no longer help run this game".into(),
+        })
+        .await?;
+        self.sender
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "Removed from the game".into(),
+            })))
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))
+    }
+
+    /// Closes the socket of a client that kept sending messages too fast
+    pub async fn disconnect_flooding(&mut self) -> AppResult<()> {
+        self.sender
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "Too many messages".into(),
+            })))
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))
+    }
+
     pub async fn close(&mut self) -> AppResult<()> {
         self.sender
             .send(Message::Close(None))
@@ -249,9 +866,12 @@ impl GameMessageService {
         .await
     }
 
-    pub async fn game_state(&mut self) -> AppResult<()> {
+    pub async fn game_state(&mut self, recipient: &PlayerType) -> AppResult<()> {
         let state = self.game_repo.get_state(&self.game_id).await?;
-        self.send(GameMessage::StateChange { state }).await
+        self.send(GameMessage::StateChange {
+            state: state.for_recipient(recipient),
+        })
+        .await
     }
 }
 
@@ -276,7 +896,7 @@ impl GameBroadcastService {
             GameAction::PlayerJoin { .. } => (),
             _ => {
                 let state = self.game_repo.get_state(&self.game_id).await?;
-                self.broadcast(GameMessage::StateChange { state })?
+                self.broadcast(GameMessage::StateChange { state })
             }
         }
         Ok(())
@@ -284,25 +904,38 @@ impl GameBroadcastService {
 
     pub async fn broadcast_game_state(&self) -> AppResult<()> {
         let state = self.game_repo.get_state(&self.game_id).await?;
-        self.broadcast(GameMessage::StateChange { state })?;
+        self.broadcast(GameMessage::StateChange { state });
         Ok(())
     }
 
+    /// Closes the sockets the user has open to help run the game, once they
+    /// no longer do
+    pub fn broadcast_member_removed(&self, user_id: &Uuid) {
+        self.broadcast(GameMessage::MemberRemoved { user_id: *user_id });
+    }
+
     pub async fn broadcast_new_player(&self, player_type: &PlayerType) -> AppResult<()> {
         self.broadcast(GameMessage::NewPlayer {
             player_type: player_type.to_owned(),
-        })?;
+        });
         Ok(())
     }
 
-    pub fn broadcast(&self, message: GameMessage) -> AppResult<()> {
-        let message = serde_json::to_string(&message)?;
-        self.sender
+    /// Sends the message to every socket connected to the game
+    ///
+    /// Sending only fails when no socket is connected to any game, such as
+    /// for the idle and abandoned games the jobs tidy up, in which case there
+    /// is nobody to tell.
+    pub fn broadcast(&self, message: GameMessage) {
+        if self
+            .sender
             .send(GameBroadcast {
                 game_id: self.game_id.clone(),
                 message,
             })
-            .map_err(|e| AppError::InternalError(format!("Could not broadcast message: {}", e)))?;
-        Ok(())
+            .is_err()
+        {
+            tracing::debug!(game_id = %self.game_id, "No sockets to broadcast to");
+        }
     }
 }
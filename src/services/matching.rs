@@ -0,0 +1,119 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::models::games::AnswerKey;
+
+/// Matches answers against an answer key
+///
+/// Answers are compared case-insensitively, ignoring accents, punctuation and
+/// extra whitespace. Small typos are allowed up to the configured edit
+/// distance, but never more than a quarter of the length of the expected
+/// answer so that short answers still have to be exact.
+pub struct AnswerMatcher {
+    tolerance: usize,
+}
+
+impl AnswerMatcher {
+    pub fn new(tolerance: usize) -> Self {
+        Self { tolerance }
+    }
+
+    /// Checks the answer against the expected answer and every alias.
+    pub fn is_match(&self, answer: &str, answer_key: &AnswerKey) -> bool {
+        let answer = normalize(answer);
+        if answer.is_empty() {
+            return false;
+        }
+
+        std::iter::once(&answer_key.expected_answer)
+            .chain(answer_key.aliases.iter())
+            .map(|expected| normalize(expected))
+            .filter(|expected| !expected.is_empty())
+            .any(|expected| {
+                let tolerance = self.tolerance.min(expected.chars().count() / 4);
+                edit_distance(&answer, &expected) <= tolerance
+            })
+    }
+}
+
+/// Lowercases the value and strips accents, punctuation and extra whitespace.
+fn normalize(value: &str) -> String {
+    let stripped: String = value
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+
+    stripped.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer_key(expected_answer: &str, aliases: &[&str]) -> AnswerKey {
+        AnswerKey {
+            image_index: 0,
+            expected_answer: expected_answer.into(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matching_ignores_case_accents_and_punctuation() {
+        let matcher = AnswerMatcher::new(0);
+        let key = answer_key("Crème Brûlée", &[]);
+
+        assert!(matcher.is_match("creme brulee", &key));
+        assert!(matcher.is_match("  CRÈME-brûlée! ", &key));
+    }
+
+    #[test]
+    fn matching_accepts_aliases() {
+        let matcher = AnswerMatcher::new(0);
+        let key = answer_key("The Eiffel Tower", &["Eiffel Tower", "La Tour Eiffel"]);
+
+        assert!(matcher.is_match("la tour eiffel", &key));
+        assert!(!matcher.is_match("the louvre", &key));
+    }
+
+    #[test]
+    fn matching_allows_typos_within_tolerance() {
+        let key = answer_key("Mississippi", &[]);
+
+        assert!(AnswerMatcher::new(2).is_match("missisipi", &key));
+        assert!(!AnswerMatcher::new(1).is_match("missisipi", &key));
+    }
+
+    #[test]
+    fn short_answers_must_be_exact() {
+        let key = answer_key("cat", &[]);
+
+        assert!(!AnswerMatcher::new(2).is_match("car", &key));
+        assert!(!AnswerMatcher::new(2).is_match("", &key));
+    }
+
+    #[test]
+    fn edit_distance_counts_characters() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("é", "e"), 1);
+    }
+}
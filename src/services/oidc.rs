@@ -0,0 +1,908 @@
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        identities::NewIdentity,
+        users::{NewUser, User},
+    },
+    redact::Redacted,
+    repositories::{identities::IdentityRepo, users::UserRepo},
+    services::auth::AuthService,
+};
+
+/// Longest the provider gets to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shortest time between fetches of the signing keys of the provider, which
+/// are fetched again when a token is signed with a key not seen before
+const KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Algorithms ID tokens may be signed with, which leaves out the ones keyed
+/// with the client secret
+const SIGNING_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// How the app is registered at an OpenID Connect provider
+#[derive(Clone)]
+pub struct OidcSettings {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Where the provider sends users back to, `/auth/oidc/callback`
+    pub redirect_url: String,
+    /// Scopes asked of the provider, separated by spaces
+    pub scopes: String,
+}
+
+impl Debug for OidcSettings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OidcSettings")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| Redacted),
+            )
+            .field("redirect_url", &self.redirect_url)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+/// The endpoints of the provider, from its discovery document
+#[derive(Clone, Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct Keys {
+    set: JwkSet,
+    fetched: Instant,
+}
+
+/// A login sent to the provider, kept in the session until the provider
+/// sends the user back
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PendingLogin {
+    /// Ties the callback to the session the login started in
+    state: String,
+    /// Ties the ID token to the login
+    nonce: String,
+    /// The PKCE code verifier, which proves to the provider that whoever
+    /// redeems the code started the login
+    verifier: String,
+}
+
+/// What the provider sends the user back with
+#[derive(Clone, Debug, Deserialize)]
+pub struct Callback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// The identity the provider vouched for in an ID token
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdentityClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    nonce: Option<String>,
+}
+
+impl IdentityClaims {
+    /// The email, when the provider says the user proved they own it
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Logs users in at an OpenID Connect provider with the authorization code
+/// flow and PKCE
+///
+/// The discovery document is fetched on first use and kept, the signing keys
+/// are fetched again when the provider rotates them.
+#[derive(Clone, Debug)]
+pub struct OidcClient {
+    settings: Arc<OidcSettings>,
+    http: reqwest::Client,
+    discovery: Arc<OnceCell<Discovery>>,
+    keys: Arc<RwLock<Option<Keys>>>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> AppResult<Self> {
+        Ok(Self {
+            settings: Arc::new(settings),
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            discovery: Arc::new(OnceCell::new()),
+            keys: Arc::new(RwLock::new(None)),
+        })
+    }
+
+    /// Starts a login, returning the address at the provider to send the
+    /// user to and what to keep in their session until they come back
+    pub async fn authorize(&self) -> AppResult<(String, PendingLogin)> {
+        let discovery = self.discovery().await?;
+        let pending = PendingLogin {
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+        };
+
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.settings.client_id),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("scope", &self.settings.scopes),
+                ("state", &pending.state),
+                ("nonce", &pending.nonce),
+                ("code_challenge", &code_challenge(&pending.verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| AppError::InternalError(format!("Invalid authorization endpoint: {}", e)))?;
+
+        Ok((url.into(), pending))
+    }
+
+    /// Finishes the login the user came back from, redeeming the code for an
+    /// ID token and checking it
+    pub async fn finish(
+        &self,
+        pending: &PendingLogin,
+        callback: Callback,
+    ) -> AppResult<IdentityClaims> {
+        if let Some(error) = callback.error {
+            return Err(AppError::AuthenticationError(format!(
+                "The login was refused: {}",
+                callback.error_description.unwrap_or(error)
+            )));
+        }
+        if callback.state.as_deref() != Some(&pending.state) {
+            return Err(AppError::AuthenticationError(
+                "The login does not match the one in progress, try again".into(),
+            ));
+        }
+        let Some(code) = callback.code else {
+            return Err(AppError::AuthenticationError(
+                "The login did not return a code".into(),
+            ));
+        };
+
+        let id_token = self.redeem(&code, pending).await?;
+        let claims = self.verify(&id_token).await?;
+        if claims.nonce.as_deref() != Some(&pending.nonce) {
+            return Err(invalid_token("it was issued for another login"));
+        }
+
+        Ok(claims)
+    }
+
+    async fn discovery(&self) -> AppResult<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.settings.issuer.trim_end_matches('/')
+                );
+                let discovery: Discovery = self
+                    .http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                if discovery.issuer.trim_end_matches('/')
+                    != self.settings.issuer.trim_end_matches('/')
+                {
+                    return Err(AppError::InternalError(format!(
+                        "The provider at {} calls itself {}",
+                        self.settings.issuer, discovery.issuer
+                    )));
+                }
+                Ok(discovery)
+            })
+            .await
+    }
+
+    /// Redeems the authorization code at the token endpoint for an ID token
+    async fn redeem(&self, code: &str, pending: &PendingLogin) -> AppResult<String> {
+        let discovery = self.discovery().await?;
+        let mut request = self.http.post(&discovery.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("code_verifier", &pending.verifier),
+            ("client_id", &self.settings.client_id),
+        ]);
+        if let Some(secret) = &self.settings.client_secret {
+            request = request.basic_auth(&self.settings.client_id, Some(secret));
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let reason = match response.json::<TokenError>().await {
+                Ok(e) => e.error_description.unwrap_or(e.error),
+                Err(_) => "the code could not be redeemed".into(),
+            };
+            return Err(AppError::AuthenticationError(format!(
+                "The login was refused: {}",
+                reason
+            )));
+        }
+
+        response
+            .json::<TokenResponse>()
+            .await?
+            .id_token
+            .ok_or_else(|| AppError::AuthenticationError("No ID token was returned".into()))
+    }
+
+    /// Checks the signature of the ID token and that it was issued by the
+    /// provider to this client and has not expired
+    async fn verify(&self, id_token: &str) -> AppResult<IdentityClaims> {
+        let discovery = self.discovery().await?;
+        let header = decode_header(id_token).map_err(invalid_token)?;
+        if !SIGNING_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid_token(format!("it is signed with {:?}", header.alg)));
+        }
+        let key = self.key(discovery, header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&key).map_err(invalid_token)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        Ok(decode::<IdentityClaims>(id_token, &key, &validation)
+            .map_err(invalid_token)?
+            .claims)
+    }
+
+    /// The signing key with the ID, or the only key when the token names
+    /// none
+    async fn key(&self, discovery: &Discovery, kid: Option<&str>) -> AppResult<Jwk> {
+        if let Some(key) = self
+            .keys
+            .read()
+            .await
+            .as_ref()
+            .and_then(|k| find_key(&k.set, kid))
+        {
+            return Ok(key);
+        }
+
+        let mut keys = self.keys.write().await;
+        if keys
+            .as_ref()
+            .is_none_or(|k| k.fetched.elapsed() >= KEY_REFRESH_INTERVAL)
+        {
+            let set = self
+                .http
+                .get(&discovery.jwks_uri)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            *keys = Some(Keys {
+                set,
+                fetched: Instant::now(),
+            });
+        }
+
+        keys.as_ref()
+            .and_then(|k| find_key(&k.set, kid))
+            .ok_or_else(|| invalid_token("it is signed with an unknown key"))
+    }
+}
+
+fn find_key(set: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => set.find(kid).cloned(),
+        None => match set.keys.as_slice() {
+            [key] => Some(key.clone()),
+            _ => None,
+        },
+    }
+}
+
+/// 32 random bytes, which also makes a code verifier of the length PKCE
+/// asks for
+fn random_string() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// The S256 code challenge of a PKCE code verifier
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn invalid_token(reason: impl std::fmt::Display) -> AppError {
+    AppError::AuthenticationError(format!("The ID token was refused, {}", reason))
+}
+
+/// The account an identity logged in to, and how it came to
+#[derive(Clone, Debug)]
+pub enum Account {
+    /// The identity was linked to the account already
+    Existing(User),
+    /// The identity was linked to the account by this login
+    Linked(User),
+    /// The account was created for the identity by this login
+    Created(User),
+}
+
+impl Account {
+    pub fn user(&self) -> &User {
+        match self {
+            Account::Existing(user) | Account::Linked(user) | Account::Created(user) => user,
+        }
+    }
+}
+
+/// Logs users in to their accounts with the identities linked to them
+#[derive(Clone, Debug)]
+pub struct OidcLogin {
+    pub client: OidcClient,
+    /// The provider as shown on the login page
+    pub provider_name: String,
+    /// Whether an account is created for an identity linked to none
+    pub create_accounts: bool,
+    /// Whether an identity is linked to the verified account that has its
+    /// email
+    pub link_by_email: bool,
+}
+
+impl OidcLogin {
+    /// The account the identity logs in to, linking the identity to the
+    /// logged in user or, when allowed, to the account with its email or a new
+    /// account. `None` when the identity has no account.
+    pub async fn account(
+        &self,
+        users: &UserRepo,
+        identities: &IdentityRepo,
+        claims: &IdentityClaims,
+        logged_in: Option<Uuid>,
+    ) -> AppResult<Option<Account>> {
+        if let Some(identity) = identities.get(&claims.iss, &claims.sub).await? {
+            if logged_in.is_some_and(|user_id| user_id != identity.user_id) {
+                return Err(AppError::ValidationError(
+                    "This identity is already linked to another account".into(),
+                ));
+            }
+            return Ok(Some(Account::Existing(users.get(identity.user_id).await?)));
+        }
+
+        let to_link = match (logged_in, claims.verified_email()) {
+            (Some(user_id), _) => Some(users.get(user_id).await?),
+            (None, Some(email)) if self.link_by_email => {
+                match users.get_by_email(email.to_owned()).await? {
+                    // Whoever registered an unverified account may not own
+                    // the email, and their password would work once linked
+                    Some(user) if !users.is_email_verified(user.id).await? => {
+                        return Err(AppError::ValidationError(format!(
+                            "An account with this email has not been verified, verify it then log in with {} again",
+                            self.provider_name
+                        )));
+                    }
+                    user => user,
+                }
+            }
+            _ => None,
+        };
+        if let Some(user) = to_link {
+            link(identities, &user, claims).await?;
+            return Ok(Some(Account::Linked(user)));
+        }
+
+        if !self.create_accounts {
+            return Ok(None);
+        }
+        let Some(email) = claims.verified_email() else {
+            return Err(AppError::AuthorizationError(format!(
+                "{} did not vouch for your email, which a new account needs",
+                self.provider_name
+            )));
+        };
+        AuthService::validate_email(email)?;
+        if users.get_by_email(email.to_owned()).await?.is_some() {
+            return Err(AppError::ValidationError(format!(
+                "An account already uses this email, log in with your password then with {} to link them",
+                self.provider_name
+            )));
+        }
+
+        // Nobody knows the password, one can be set with a password reset
+        let user = users
+            .insert(NewUser {
+                email: email.to_owned(),
+                password: AuthService::hash_password(&random_string())?,
+            })
+            .await?;
+        users.mark_email_verified(user.id).await?;
+        link(identities, &user, claims).await?;
+
+        Ok(Some(Account::Created(user)))
+    }
+}
+
+async fn link(identities: &IdentityRepo, user: &User, claims: &IdentityClaims) -> AppResult<()> {
+    identities
+        .insert(NewIdentity {
+            user_id: user.id,
+            issuer: claims.iss.clone(),
+            subject: claims.sub.clone(),
+            email: claims.email.clone(),
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        sync::Mutex,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use axum::{
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode},
+        response::{IntoResponse, Redirect, Response},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "namethat";
+    const CLIENT_SECRET: &str = "client secret";
+    const REDIRECT_URL: &str = "http://localhost:3000/auth/oidc/callback";
+
+    /// A key for signing test ID tokens and nothing else
+    const SIGNING_KEY: &str = include_str!("testdata/oidc-key.pem");
+    /// The modulus of the signing key, as published in the JWKS
+    const SIGNING_KEY_MODULUS: &str = "u5NKuTOS9s9L3uC45NvYqnfs6n1juJtCfaGigWZlYDMoYh1y0jZxOn0J0wJu_DbHQ3TMXwfe7MLRZl5FWOjpW_kwWcW8B5xHHnfPOSJsgNLtD-ZGEOnDd0e7UjvYz3LaXvpD9fC7Ljr-jOWI7IgTf12d-waqSJ4qKE5qxZMQsyGjckSMBH4b9uJaqfbXzA_6iyPCkruIa6tuThxQC4Bujdktn2uwzA916x54vnGgHAz7_qEsnWH58EuGgXAPf7dQqASbdZoynQ8ldBeY7LrDM8CB3-R8xpx8NsVS2xYvEG1HvySa26YQZfau9ITeS0MzoiYGWGuM3FvoM-eRPi_iLQ";
+
+    /// A login the mock provider has handed out a code for
+    struct Grant {
+        challenge: String,
+        nonce: String,
+        redirect_uri: String,
+    }
+
+    /// An OpenID Connect provider that logs everyone in as the same user
+    #[derive(Default)]
+    struct MockProvider {
+        issuer: String,
+        grants: Mutex<HashMap<String, Grant>>,
+        /// Claims put in the ID tokens in place of the right ones
+        overrides: Mutex<Value>,
+    }
+
+    async fn discovery(State(provider): State<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn jwks() -> Json<Value> {
+        Json(json!({
+            "keys": [{
+                "kty": "RSA",
+                "kid": "test",
+                "use": "sig",
+                "alg": "RS256",
+                "n": SIGNING_KEY_MODULUS,
+                "e": "AQAB",
+            }]
+        }))
+    }
+
+    async fn authorize(
+        State(provider): State<Arc<MockProvider>>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Response {
+        if params["client_id"] != CLIENT_ID || params["code_challenge_method"] != "S256" {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        let code = random_string();
+        provider.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                challenge: params["code_challenge"].clone(),
+                nonce: params["nonce"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+            },
+        );
+
+        Redirect::to(&format!(
+            "{}?code={}&state={}",
+            params["redirect_uri"], code, params["state"]
+        ))
+        .into_response()
+    }
+
+    async fn token(
+        State(provider): State<Arc<MockProvider>>,
+        headers: HeaderMap,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Response {
+        let refuse = |error: &str| {
+            (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+        };
+        let credentials = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", CLIENT_ID, CLIENT_SECRET))
+        );
+        if headers.get("authorization").and_then(|h| h.to_str().ok()) != Some(&credentials) {
+            return refuse("invalid_client");
+        }
+        let Some(grant) = provider.grants.lock().unwrap().remove(&params["code"]) else {
+            return refuse("invalid_grant");
+        };
+        if grant.redirect_uri != params["redirect_uri"]
+            || grant.challenge != code_challenge(&params["code_verifier"])
+        {
+            return refuse("invalid_grant");
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut claims = json!({
+            "iss": provider.issuer,
+            "sub": "user-1",
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 60,
+            "nonce": grant.nonce,
+            "email": "sso@example.com",
+            "email_verified": true,
+        });
+        if let Value::Object(overrides) = provider.overrides.lock().unwrap().clone() {
+            claims.as_object_mut().unwrap().extend(overrides);
+        }
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test".into());
+        let key = EncodingKey::from_rsa_pem(SIGNING_KEY.as_bytes()).unwrap();
+
+        Json(json!({
+            "access_token": "unused",
+            "token_type": "Bearer",
+            "id_token": encode(&header, &claims, &key).unwrap(),
+        }))
+        .into_response()
+    }
+
+    /// Serves a mock provider and returns a client registered with it
+    fn serve() -> (Arc<MockProvider>, OidcClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let provider = Arc::new(MockProvider {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let client = OidcClient::new(OidcSettings {
+            issuer: provider.issuer.clone(),
+            client_id: CLIENT_ID.into(),
+            client_secret: Some(CLIENT_SECRET.into()),
+            redirect_url: REDIRECT_URL.into(),
+            scopes: "openid email".into(),
+        })
+        .unwrap();
+        (provider, client)
+    }
+
+    /// Follows the authorization URL the way a browser would, returning where
+    /// the provider sent the user back with
+    async fn log_in_at_provider(url: &str) -> Callback {
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap();
+        let location = response.headers()["location"].to_str().unwrap();
+        let url = Url::parse(location).unwrap();
+        assert!(location.starts_with(REDIRECT_URL));
+
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        Callback {
+            code: params.get("code").cloned(),
+            state: params.get("state").cloned(),
+            error: None,
+            error_description: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn logins_go_through_the_provider_with_pkce() {
+        let (provider, client) = serve();
+
+        let (url, pending) = client.authorize().await.unwrap();
+        let params: HashMap<_, _> = Url::parse(&url)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(params["code_challenge"], code_challenge(&pending.verifier));
+        assert_eq!(params["scope"], "openid email");
+        let callback = log_in_at_provider(&url).await;
+        let claims = client.finish(&pending, callback).await.unwrap();
+
+        assert_eq!(claims.iss, provider.issuer);
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.verified_email(), Some("sso@example.com"));
+    }
+
+    #[tokio::test]
+    async fn callbacks_must_match_the_login_in_progress() {
+        let (_provider, client) = serve();
+        let (url, pending) = client.authorize().await.unwrap();
+        let callback = log_in_at_provider(&url).await;
+
+        let (_, other) = client.authorize().await.unwrap();
+        assert!(client.finish(&other, callback.clone()).await.is_err());
+        let refused = Callback {
+            error: Some("access_denied".into()),
+            ..callback.clone()
+        };
+        assert!(client.finish(&pending, refused).await.is_err());
+        // The code is still good, nothing above redeemed it
+        assert!(client.finish(&pending, callback).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn codes_are_only_redeemed_with_the_verifier() {
+        let (_provider, client) = serve();
+        let (url, pending) = client.authorize().await.unwrap();
+        let callback = log_in_at_provider(&url).await;
+
+        let stolen = PendingLogin {
+            verifier: random_string(),
+            ..pending.clone()
+        };
+
+        assert!(matches!(
+            client.finish(&stolen, callback).await,
+            Err(AppError::AuthenticationError(message)) if message.contains("invalid_grant")
+        ));
+    }
+
+    #[tokio::test]
+    async fn id_tokens_for_other_clients_logins_or_issuers_are_refused() {
+        let (provider, client) = serve();
+
+        for overrides in [
+            json!({ "aud": "another-client" }),
+            json!({ "nonce": "another-login" }),
+            json!({ "iss": "http://evil.example.com" }),
+            json!({ "exp": 1 }),
+        ] {
+            *provider.overrides.lock().unwrap() = overrides.clone();
+            let (url, pending) = client.authorize().await.unwrap();
+            let callback = log_in_at_provider(&url).await;
+
+            assert!(
+                matches!(
+                    client.finish(&pending, callback).await,
+                    Err(AppError::AuthenticationError(_))
+                ),
+                "{} was accepted",
+                overrides
+            );
+        }
+    }
+
+    /// Connects to the database named by `TEST_DATABASE_URL`
+    async fn test_client() -> PgPool {
+        dotenv::dotenv().ok();
+        let url = std::env::var("TEST_DATABASE_URL").expect("Did not find database URL");
+        let client = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!().run(&client).await.unwrap();
+        client
+    }
+
+    fn claims(email: &str, email_verified: bool) -> IdentityClaims {
+        IdentityClaims {
+            iss: "http://provider.example.com".into(),
+            sub: Uuid::from_u128(rand::random()).to_string(),
+            email: Some(email.into()),
+            email_verified,
+            nonce: None,
+        }
+    }
+
+    /// Logs in with identities from a provider that is never reached
+    fn login(create_accounts: bool, link_by_email: bool) -> OidcLogin {
+        OidcLogin {
+            client: OidcClient::new(OidcSettings {
+                issuer: "http://provider.example.com".into(),
+                client_id: CLIENT_ID.into(),
+                client_secret: None,
+                redirect_url: REDIRECT_URL.into(),
+                scopes: "openid email".into(),
+            })
+            .unwrap(),
+            provider_name: "SSO".into(),
+            create_accounts,
+            link_by_email,
+        }
+    }
+
+    fn unique_email() -> String {
+        format!("oidc-{}@example.com", Uuid::from_u128(rand::random()))
+    }
+
+    #[tokio::test]
+    async fn accounts_are_created_for_new_identities_when_configured() {
+        let client = test_client().await;
+        let (users, identities) = (UserRepo::new(client.clone()), IdentityRepo::new(client));
+        let mut login = login(false, false);
+        let email = unique_email();
+        let identity = claims(&email, true);
+
+        let account = login.account(&users, &identities, &identity, None).await;
+        assert!(matches!(account, Ok(None)));
+
+        login.create_accounts = true;
+        let unverified = claims(&unique_email(), false);
+        let account = login.account(&users, &identities, &unverified, None).await;
+        assert!(matches!(account, Err(AppError::AuthorizationError(_))));
+
+        let created = login
+            .account(&users, &identities, &identity, None)
+            .await
+            .unwrap();
+        let Some(Account::Created(user)) = created else {
+            panic!("No account was created: {:?}", created);
+        };
+        assert_eq!(user.email, email);
+        assert!(users.is_email_verified(user.id).await.unwrap());
+
+        let again = login
+            .account(&users, &identities, &identity, None)
+            .await
+            .unwrap();
+        assert!(matches!(again, Some(Account::Existing(u)) if u.id == user.id));
+        users.delete(user.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn identities_are_linked_to_the_logged_in_user_or_by_email() {
+        let client = test_client().await;
+        let (users, identities) = (UserRepo::new(client.clone()), IdentityRepo::new(client));
+        let mut login = login(true, false);
+        let user = users
+            .insert(NewUser {
+                email: unique_email(),
+                password: AuthService::hash_password("password").unwrap(),
+            })
+            .await
+            .unwrap();
+        let other = users
+            .insert(NewUser {
+                email: unique_email(),
+                password: AuthService::hash_password("password").unwrap(),
+            })
+            .await
+            .unwrap();
+
+        // Without linking by email, an account is not taken over by whoever
+        // has an identity with its email
+        let same_email = claims(&user.email, true);
+        let account = login.account(&users, &identities, &same_email, None).await;
+        assert!(matches!(account, Err(AppError::ValidationError(_))));
+
+        // Nor is an account someone registered with the email but never
+        // verified, since they would keep logging in with their password
+        login.link_by_email = true;
+        let account = login.account(&users, &identities, &same_email, None).await;
+        assert!(matches!(account, Err(AppError::ValidationError(_))));
+        assert!(identities.list_for_user(user.id).await.unwrap().is_empty());
+
+        users.mark_email_verified(user.id).await.unwrap();
+        let account = login
+            .account(&users, &identities, &same_email, None)
+            .await
+            .unwrap();
+        assert!(matches!(account, Some(Account::Linked(u)) if u.id == user.id));
+
+        let another = claims("someone-else@example.com", false);
+        let account = login
+            .account(&users, &identities, &another, Some(user.id))
+            .await
+            .unwrap();
+        assert!(matches!(account, Some(Account::Linked(u)) if u.id == user.id));
+        assert_eq!(identities.list_for_user(user.id).await.unwrap().len(), 2);
+
+        let account = login
+            .account(&users, &identities, &another, Some(other.id))
+            .await;
+        assert!(matches!(account, Err(AppError::ValidationError(_))));
+
+        users.delete(user.id).await.unwrap();
+        users.delete(other.id).await.unwrap();
+        assert!(identities
+            .get(&another.iss, &another.sub)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::models::games::{GameAction, GameRole};
+
+/// Checks whether a role may take an action in a game
+///
+/// This is the permission matrix for every [`GameAction`]. Whether the action
+/// makes sense for the current state of the game is still checked by the
+/// game service.
+pub fn is_allowed(role: &GameRole, action: &GameAction) -> bool {
+    use GameRole::*;
+
+    let allowed: &[GameRole] = match action {
+        GameAction::PlayerJoin { .. } => &[Owner, CoHost, Moderator, Player, Observer],
+        GameAction::StartRound { .. } => &[Owner, CoHost],
+        GameAction::SetReady { .. } => &[Player],
+        GameAction::Pause => &[Owner, CoHost],
+        GameAction::Resume => &[Owner, CoHost],
+        GameAction::UserAnswer { .. } => &[Player],
+        GameAction::WithdrawAnswer { .. } => &[Player],
+        GameAction::CloseAnswers { .. } => &[Owner, CoHost],
+        GameAction::RevealAnswer { .. } => &[Owner, CoHost, Moderator],
+        GameAction::RevealAll { .. } => &[Owner, CoHost],
+        GameAction::LikeAnswer { .. } => &[Owner, CoHost, Moderator, Player],
+        GameAction::EndRound { .. } => &[Owner, CoHost],
+        GameAction::EndGame => &[Owner, CoHost],
+        GameAction::RequestPromotion => &[Observer],
+        GameAction::PromotePlayer { .. } => &[Owner, CoHost],
+        GameAction::RemoveAnswer { .. } => &[Owner, CoHost, Moderator],
+    };
+
+    allowed.contains(role)
+}
+
+/// Checks whether an action may be taken while the game is paused
+///
+/// Only joining, resuming or ending the game and moderating answers are
+/// allowed, everything else waits for the game to resume.
+pub fn is_allowed_while_paused(action: &GameAction) -> bool {
+    matches!(
+        action,
+        GameAction::PlayerJoin { .. }
+            | GameAction::Resume
+            | GameAction::EndGame
+            | GameAction::RemoveAnswer { .. }
+    )
+}
+
+/// Checks whether a role may invite other users to help run the game
+pub fn can_manage_members(role: &GameRole) -> bool {
+    *role == GameRole::Owner
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn end_round() -> GameAction {
+        GameAction::EndRound {
+            round_id: Uuid::nil(),
+            winner: None,
+            answer_id: None,
+        }
+    }
+
+    fn start_round() -> GameAction {
+        GameAction::StartRound {
+            round: 1,
+            force: false,
+        }
+    }
+
+    fn user_answer() -> GameAction {
+        GameAction::UserAnswer {
+            round_id: Uuid::nil(),
+            answer: "answer".into(),
+            answer_id: None,
+        }
+    }
+
+    #[test]
+    fn hosts_run_the_game() {
+        for role in [GameRole::Owner, GameRole::CoHost] {
+            assert!(is_allowed(&role, &start_round()));
+            assert!(is_allowed(&role, &end_round()));
+            assert!(is_allowed(&role, &GameAction::EndGame));
+            assert!(is_allowed(&role, &GameAction::Pause));
+            assert!(is_allowed(&role, &GameAction::Resume));
+            assert!(!is_allowed(&role, &user_answer()));
+        }
+    }
+
+    #[test]
+    fn moderators_can_reveal_and_remove_answers_but_not_run_the_game() {
+        let role = GameRole::Moderator;
+        let answer_id = Uuid::nil();
+
+        assert!(is_allowed(&role, &GameAction::RevealAnswer { answer_id }));
+        assert!(is_allowed(&role, &GameAction::RemoveAnswer { answer_id }));
+        assert!(!is_allowed(&role, &start_round()));
+        assert!(!is_allowed(&role, &end_round()));
+        assert!(!is_allowed(&role, &user_answer()));
+        assert!(!is_allowed(&role, &GameAction::Pause));
+    }
+
+    #[test]
+    fn players_answer_and_like() {
+        let role = GameRole::Player;
+
+        assert!(is_allowed(&role, &user_answer()));
+        assert!(is_allowed(&role, &GameAction::SetReady { ready: true }));
+        assert!(is_allowed(
+            &role,
+            &GameAction::LikeAnswer {
+                answer_id: Uuid::nil()
+            }
+        ));
+        assert!(!is_allowed(
+            &role,
+            &GameAction::CloseAnswers {
+                round_id: Uuid::nil()
+            }
+        ));
+        assert!(!is_allowed(&role, &GameAction::RequestPromotion));
+    }
+
+    #[test]
+    fn observers_can_only_ask_to_play() {
+        let role = GameRole::Observer;
+
+        assert!(is_allowed(&role, &GameAction::RequestPromotion));
+        assert!(!is_allowed(&role, &user_answer()));
+        assert!(!is_allowed(&role, &GameAction::SetReady { ready: true }));
+        assert!(!is_allowed(
+            &role,
+            &GameAction::LikeAnswer {
+                answer_id: Uuid::nil()
+            }
+        ));
+    }
+
+    #[test]
+    fn paused_games_only_allow_resuming_ending_and_moderating() {
+        assert!(is_allowed_while_paused(&GameAction::Resume));
+        assert!(is_allowed_while_paused(&GameAction::EndGame));
+        assert!(is_allowed_while_paused(&GameAction::RemoveAnswer {
+            answer_id: Uuid::nil()
+        }));
+        assert!(!is_allowed_while_paused(&user_answer()));
+        assert!(!is_allowed_while_paused(&start_round()));
+        assert!(!is_allowed_while_paused(&GameAction::LikeAnswer {
+            answer_id: Uuid::nil()
+        }));
+    }
+
+    #[test]
+    fn only_the_owner_manages_members() {
+        assert!(can_manage_members(&GameRole::Owner));
+        assert!(!can_manage_members(&GameRole::CoHost));
+        assert!(!can_manage_members(&GameRole::Moderator));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use uuid::Uuid;
+
+/// Keeps track of the players and hosts with an open websocket
+///
+/// A player may have several sockets open, for example in two tabs, so the
+/// sockets are counted. Players keep their id when reconnecting through the
+/// session mapping, which lets a dropped connection be picked up again within
+/// the grace period without the player ever being marked inactive. Hosts are
+/// counted per game, since the owner and co-host both run the same game.
+///
+/// Only the sockets of this process are counted. Each process records the
+/// players and hosts it has connected in the database every heartbeat, so
+/// that a player connected to any of them is not taken to be gone.
+#[derive(Clone, Debug)]
+pub struct PresenceTracker {
+    connections: Arc<Mutex<HashMap<Uuid, usize>>>,
+    hosts: Arc<Mutex<HashMap<Uuid, usize>>>,
+    grace_period: Duration,
+    heartbeat_interval: Duration,
+}
+
+impl PresenceTracker {
+    pub fn new(grace_period: Duration, heartbeat_interval: Duration) -> Self {
+        Self {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            grace_period,
+            heartbeat_interval,
+        }
+    }
+
+    /// How long a player may be disconnected before they are marked inactive
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// How often the connected players and hosts are recorded
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    /// How long since a player or host was last recorded before they are
+    /// taken to be gone, allowing for a missed heartbeat
+    pub fn timeout(&self) -> Duration {
+        self.grace_period + self.heartbeat_interval * 2
+    }
+
+    /// Counts the socket of the player, or of a host of the game when there
+    /// is no player, as connected until the guard is dropped
+    pub fn track(&self, player_id: Option<Uuid>, game_id: Uuid) -> PresenceGuard {
+        match player_id {
+            Some(player_id) => self.connect(&player_id),
+            None => self.connect_host(&game_id),
+        }
+
+        PresenceGuard {
+            presence: self.clone(),
+            player_id,
+            game_id,
+        }
+    }
+
+    pub fn connect(&self, player_id: &Uuid) {
+        increment(&self.connections, player_id);
+    }
+
+    pub fn disconnect(&self, player_id: &Uuid) {
+        decrement(&self.connections, player_id);
+    }
+
+    pub fn is_connected(&self, player_id: &Uuid) -> bool {
+        self.connections.lock().unwrap().contains_key(player_id)
+    }
+
+    /// The ids of every player with an open websocket
+    pub fn connected(&self) -> Vec<Uuid> {
+        self.connections.lock().unwrap().keys().copied().collect()
+    }
+
+    pub fn connect_host(&self, game_id: &Uuid) {
+        increment(&self.hosts, game_id);
+    }
+
+    pub fn disconnect_host(&self, game_id: &Uuid) {
+        decrement(&self.hosts, game_id);
+    }
+
+    pub fn is_host_connected(&self, game_id: &Uuid) -> bool {
+        self.hosts.lock().unwrap().contains_key(game_id)
+    }
+
+    /// The ids of every game with a host connected
+    pub fn connected_hosts(&self) -> Vec<Uuid> {
+        self.hosts.lock().unwrap().keys().copied().collect()
+    }
+}
+
+/// A connected socket, which is counted as disconnected when dropped
+#[must_use]
+#[derive(Debug)]
+pub struct PresenceGuard {
+    presence: PresenceTracker,
+    player_id: Option<Uuid>,
+    game_id: Uuid,
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        match self.player_id {
+            Some(player_id) => self.presence.disconnect(&player_id),
+            None => self.presence.disconnect_host(&self.game_id),
+        }
+    }
+}
+
+fn increment(counts: &Mutex<HashMap<Uuid, usize>>, id: &Uuid) {
+    *counts.lock().unwrap().entry(*id).or_insert(0) += 1;
+}
+
+fn decrement(counts: &Mutex<HashMap<Uuid, usize>>, id: &Uuid) {
+    let mut counts = counts.lock().unwrap();
+    if let Some(count) = counts.get_mut(id) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_stay_connected_until_every_socket_is_closed() {
+        let presence = PresenceTracker::new(Duration::from_secs(10), Duration::from_secs(60));
+        let player_id = Uuid::from_u128(1);
+
+        presence.connect(&player_id);
+        presence.connect(&player_id);
+        presence.disconnect(&player_id);
+        assert!(presence.is_connected(&player_id));
+
+        presence.disconnect(&player_id);
+        assert!(!presence.is_connected(&player_id));
+        assert!(presence.connected().is_empty());
+    }
+
+    #[test]
+    fn hosts_are_tracked_per_game() {
+        let presence = PresenceTracker::new(Duration::from_secs(10), Duration::from_secs(60));
+        let game_id = Uuid::from_u128(1);
+
+        presence.connect_host(&game_id);
+
+        assert!(presence.is_host_connected(&game_id));
+        assert!(!presence.is_connected(&game_id));
+        assert_eq!(presence.connected_hosts(), vec![game_id]);
+    }
+
+    #[test]
+    fn sockets_are_disconnected_when_their_guard_is_dropped() {
+        let presence = PresenceTracker::new(Duration::from_secs(10), Duration::from_secs(60));
+        let (player_id, game_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+
+        let player = presence.track(Some(player_id), game_id);
+        let host = presence.track(None, game_id);
+        assert!(presence.is_connected(&player_id));
+        assert!(presence.is_host_connected(&game_id));
+
+        drop(player);
+        drop(host);
+        assert!(!presence.is_connected(&player_id));
+        assert!(!presence.is_host_connected(&game_id));
+    }
+
+    #[test]
+    fn disconnecting_an_unknown_player_is_ignored() {
+        let presence = PresenceTracker::new(Duration::from_secs(10), Duration::from_secs(60));
+
+        presence.disconnect(&Uuid::from_u128(1));
+
+        assert!(presence.connected().is_empty());
+    }
+}
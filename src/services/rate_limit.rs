@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::error::{AppError, AppResult};
+
+/// Buckets kept before some are dropped, so that callers that come and go do
+/// not grow the limiter forever
+const MAX_BUCKETS: usize = 10_000;
+
+/// How many attempts a caller may make at once, and how fast they get them
+/// back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Rate {
+    pub fn per_second(burst: u32, per_second: u32) -> Self {
+        Self {
+            burst,
+            per_second: per_second as f64,
+        }
+    }
+
+    pub fn per_minute(burst: u32, per_minute: u32) -> Self {
+        Self {
+            burst,
+            per_second: per_minute as f64 / 60.0,
+        }
+    }
+}
+
+/// A token bucket, which starts full and refills continuously at the rate
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available
+    pub fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let wait = (1.0 - self.tokens) / self.rate.per_second;
+        Err(Duration::from_secs_f64(wait))
+    }
+
+    /// Whether the bucket has refilled, and so behaves the same as a new one
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.rate.per_second >= self.rate.burst as f64
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Limits each key, such as an IP address, to the rate with a bucket of its
+/// own
+#[derive(Clone, Debug)]
+pub struct RateLimiter<K> {
+    rate: Rate,
+    capacity: usize,
+    buckets: Arc<Mutex<HashMap<K, TokenBucket>>>,
+}
+
+impl<K: Clone + Eq + Hash> RateLimiter<K> {
+    pub fn new(rate: Rate) -> Self {
+        Self::with_capacity(rate, MAX_BUCKETS)
+    }
+
+    /// A limiter that keeps at most `capacity` buckets
+    pub fn with_capacity(rate: Rate, capacity: usize) -> Self {
+        Self {
+            rate,
+            capacity: capacity.max(1),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes an attempt for the key, or returns how long until it may try
+    /// again
+    pub fn check(&self, key: &K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &K, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= self.capacity && !buckets.contains_key(key) {
+            self.evict(&mut buckets, now);
+        }
+
+        buckets
+            .entry(key.clone())
+            .or_insert_with(|| TokenBucket::new(self.rate, now))
+            .take(now)
+    }
+
+    /// Drops the full buckets, then the least recently used ones until a
+    /// tenth of the capacity is free, so that evicting is rare however many
+    /// callers there are
+    fn evict(&self, buckets: &mut HashMap<K, TokenBucket>, now: Instant) {
+        buckets.retain(|_, bucket| !bucket.is_full(now));
+
+        let keep = self.capacity - self.capacity.div_ceil(10);
+        if buckets.len() <= keep {
+            return;
+        }
+
+        let mut by_age: Vec<(Instant, K)> = buckets
+            .iter()
+            .map(|(key, bucket)| (bucket.updated, key.clone()))
+            .collect();
+        let evicted = by_age.len() - keep;
+        by_age.select_nth_unstable_by_key(evicted - 1, |(updated, _)| *updated);
+        for (_, key) in &by_age[..evicted] {
+            buckets.remove(key);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+/// Limits logging in and registering by the IP address of the caller and by
+/// the email they try, so that neither many accounts from one address nor
+/// one account from many addresses can be guessed at quickly
+#[derive(Clone, Debug)]
+pub struct AuthRateLimiter {
+    by_ip: RateLimiter<IpAddr>,
+    by_email: RateLimiter<String>,
+}
+
+impl AuthRateLimiter {
+    pub fn new(per_ip: Rate, per_email: Rate) -> Self {
+        Self {
+            by_ip: RateLimiter::new(per_ip),
+            by_email: RateLimiter::new(per_email),
+        }
+    }
+
+    /// Takes an attempt for the address and the email
+    ///
+    /// The email is only counted once the address is allowed, so that an
+    /// address spraying emails cannot fill the limiter with them.
+    pub fn check(&self, ip: IpAddr, email: &str) -> AppResult<()> {
+        self.by_ip
+            .check(&ip)
+            .and_then(|_| self.by_email.check(&email.trim().to_lowercase()))
+            .map_err(|wait| {
+                tracing::warn!(%ip, "Too many authentication attempts");
+                AppError::TooManyRequestsError(format!(
+                    "Too many attempts, try again in {} seconds",
+                    wait.as_secs().max(1)
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_allow_a_burst_then_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Rate::per_second(2, 1), start);
+
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Err(Duration::from_secs(1)));
+
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(bucket.take(later), Ok(()));
+        assert_eq!(bucket.take(later), Err(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn keys_are_limited_separately() {
+        let limiter = RateLimiter::new(Rate::per_minute(1, 1));
+        let now = Instant::now();
+
+        assert!(limiter.check_at(&"a", now).is_ok());
+        assert!(limiter.check_at(&"a", now).is_err());
+        assert!(limiter.check_at(&"b", now).is_ok());
+    }
+
+    #[test]
+    fn the_least_recently_used_buckets_are_dropped_at_capacity() {
+        let limiter = RateLimiter::with_capacity(Rate::per_minute(1, 1), 100);
+        let start = Instant::now();
+
+        for i in 0..1000 {
+            let now = start + Duration::from_millis(i);
+            assert!(limiter.check_at(&i, now).is_ok());
+            assert!(limiter.len() <= 100);
+        }
+
+        // The latest callers are still limited
+        let now = start + Duration::from_millis(1000);
+        assert!(limiter.check_at(&999, now).is_err());
+    }
+
+    #[test]
+    fn spraying_emails_from_one_address_keeps_the_limiter_bounded() {
+        let limiter = AuthRateLimiter::new(Rate::per_minute(5, 1), Rate::per_minute(5, 1));
+        let ip = "10.0.0.1".parse().unwrap();
+
+        for i in 0..1000 {
+            let _ = limiter.check(ip, &format!("player{}@example.com", i));
+        }
+
+        assert_eq!(limiter.by_ip.len(), 1);
+        assert_eq!(limiter.by_email.len(), 5);
+    }
+
+    #[test]
+    fn emails_are_limited_whatever_the_case() {
+        let limiter = AuthRateLimiter::new(Rate::per_minute(10, 1), Rate::per_minute(1, 1));
+
+        assert!(limiter
+            .check("10.0.0.1".parse().unwrap(), "player@example.com")
+            .is_ok());
+        let result = limiter.check("10.0.0.2".parse().unwrap(), " Player@Example.com");
+
+        assert!(matches!(result, Err(AppError::TooManyRequestsError(_))));
+    }
+}
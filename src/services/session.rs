@@ -55,6 +55,14 @@ impl SessionManager {
         Ok(())
     }
 
+    /// Logs the user out everywhere
+    pub async fn destroy_user_sessions(&self, user_id: &Uuid) -> AppResult<u64> {
+        self.session_store
+            .destroy_for_user(user_id)
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))
+    }
+
     pub async fn set_game_display_name(
         &self,
         id: &str,
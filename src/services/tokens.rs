@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::users::User,
+};
+
+/// What a token lets its holder do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify-email",
+            TokenPurpose::ResetPassword => "reset-password",
+        }
+    }
+
+    /// The part of the account the token is only good for while it is
+    /// unchanged, so that a reset link stops working once the password has
+    /// been changed, and a verification link once the email has.
+    fn binding<'a>(&self, user: &'a User) -> &'a str {
+        match self {
+            TokenPurpose::VerifyEmail => &user.email,
+            TokenPurpose::ResetPassword => &user.password,
+        }
+    }
+}
+
+/// Signs the tokens sent in emails, which are `<user id>.<expiry>.<signature>`
+///
+/// Nothing is stored for a token, the signature covers what it is for, the
+/// user and when it expires, along with the email or password hash of the
+/// user so that it cannot be used again once it has done its job.
+#[derive(Clone)]
+pub struct TokenSigner {
+    key: Vec<u8>,
+}
+
+impl TokenSigner {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    pub fn sign(&self, purpose: TokenPurpose, user: &User, ttl: Duration) -> String {
+        let expires = Utc::now().timestamp() + ttl.as_secs() as i64;
+        let signature = self.signature(purpose, &user.id, expires, purpose.binding(user));
+
+        format!(
+            "{}.{}.{}",
+            user.id.simple(),
+            expires,
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// The user a token claims to be for, which is not to be trusted until
+    /// the token has been verified
+    pub fn user_id(token: &str) -> AppResult<Uuid> {
+        let (user_id, _, _) = parts(token)?;
+        Ok(user_id)
+    }
+
+    /// Checks that the token was signed for the purpose and user and has not
+    /// expired
+    pub fn verify(&self, token: &str, purpose: TokenPurpose, user: &User) -> AppResult<()> {
+        let (user_id, expires, signature) = parts(token)?;
+
+        let mac = self.mac(purpose, &user_id, expires, purpose.binding(user));
+        if user_id != user.id || mac.verify_slice(&signature).is_err() {
+            return Err(invalid());
+        }
+        if expires < Utc::now().timestamp() {
+            return Err(AppError::ValidationError("This link has expired".into()));
+        }
+
+        Ok(())
+    }
+
+    fn signature(
+        &self,
+        purpose: TokenPurpose,
+        user_id: &Uuid,
+        expires: i64,
+        binding: &str,
+    ) -> Vec<u8> {
+        self.mac(purpose, user_id, expires, binding)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(
+        &self,
+        purpose: TokenPurpose,
+        user_id: &Uuid,
+        expires: i64,
+        binding: &str,
+    ) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        for part in [
+            "namethat-token",
+            purpose.as_str(),
+            &user_id.to_string(),
+            &expires.to_string(),
+            binding,
+        ] {
+            // Lengths keep the parts from running into one another
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part.as_bytes());
+        }
+        mac
+    }
+}
+
+impl std::fmt::Debug for TokenSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenSigner").finish_non_exhaustive()
+    }
+}
+
+fn parts(token: &str) -> AppResult<(Uuid, i64, Vec<u8>)> {
+    let mut parts = token.splitn(3, '.');
+    let (Some(user_id), Some(expires), Some(signature)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+
+    Ok((
+        user_id.parse().map_err(|_| invalid())?,
+        expires.parse().map_err(|_| invalid())?,
+        URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?,
+    ))
+}
+
+fn invalid() -> AppError {
+    AppError::ValidationError("This link is not valid".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn user() -> User {
+        User {
+            id: Uuid::from_u128(1),
+            email: "player@example.com".into(),
+            password: "$argon2id$v=19$old".into(),
+        }
+    }
+
+    #[test]
+    fn tokens_are_only_good_for_their_purpose_and_user() {
+        let signer = TokenSigner::new(b"secret");
+        let token = signer.sign(TokenPurpose::ResetPassword, &user(), HOUR);
+        let other = User {
+            id: Uuid::from_u128(2),
+            ..user()
+        };
+
+        assert_eq!(TokenSigner::user_id(&token).unwrap(), user().id);
+        assert!(signer
+            .verify(&token, TokenPurpose::ResetPassword, &user())
+            .is_ok());
+        assert!(signer
+            .verify(&token, TokenPurpose::VerifyEmail, &user())
+            .is_err());
+        assert!(signer
+            .verify(&token, TokenPurpose::ResetPassword, &other)
+            .is_err());
+        assert!(TokenSigner::new(b"other secret")
+            .verify(&token, TokenPurpose::ResetPassword, &user())
+            .is_err());
+    }
+
+    #[test]
+    fn reset_tokens_stop_working_once_the_password_changes() {
+        let signer = TokenSigner::new(b"secret");
+        let token = signer.sign(TokenPurpose::ResetPassword, &user(), HOUR);
+        let changed = User {
+            password: "$argon2id$v=19$new".into(),
+            ..user()
+        };
+
+        assert!(signer
+            .verify(&token, TokenPurpose::ResetPassword, &changed)
+            .is_err());
+    }
+
+    #[test]
+    fn expired_and_tampered_tokens_are_refused() {
+        let signer = TokenSigner::new(b"secret");
+        let token = signer.sign(TokenPurpose::VerifyEmail, &user(), HOUR);
+        let (user_id, rest) = token.split_once('.').unwrap();
+        let (expires, signature) = rest.split_once('.').unwrap();
+        let extended = format!(
+            "{}.{}.{}",
+            user_id,
+            expires.parse::<i64>().unwrap() + 1,
+            signature
+        );
+
+        assert!(signer
+            .verify(&extended, TokenPurpose::VerifyEmail, &user())
+            .is_err());
+        assert!(signer
+            .verify("not a token", TokenPurpose::VerifyEmail, &user())
+            .is_err());
+
+        let expired = signer.sign(TokenPurpose::VerifyEmail, &user(), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(matches!(
+            signer.verify(&expired, TokenPurpose::VerifyEmail, &user()),
+            Err(AppError::ValidationError(message)) if message.contains("expired")
+        ));
+    }
+}
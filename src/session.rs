@@ -230,6 +230,25 @@ impl SessionStore {
         Ok(count)
     }
 
+    /// Destroys every session the user is logged in with, such as after
+    /// their password was reset
+    ///
+    /// Returns the number of sessions destroyed.
+    pub async fn destroy_for_user(&self, user_id: &uuid::Uuid) -> Result<u64> {
+        let mut connection = self.connection().await?;
+        // Session values are stored as JSON strings within the session
+        let value = serde_json::to_string(user_id)?;
+
+        let result = sqlx::query(&self.substitute_table_name(
+            "DELETE FROM %%TABLE_NAME%% WHERE session::json -> 'data' ->> 'user_id' = $1",
+        ))
+        .bind(value)
+        .execute(&mut *connection)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
     pub async fn load_by_id(&self, id: &str) -> Result<Option<Session>> {
         let mut connection = self.connection().await?;
 
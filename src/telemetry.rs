@@ -0,0 +1,173 @@
+use axum::http::HeaderMap;
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, TracerProvider},
+    Resource,
+};
+use tracing::{Level, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::Targets, registry::LookupSpan, Layer};
+
+/// Exports the spans of the app to an OpenTelemetry collector over OTLP/HTTP
+///
+/// Spans are sent in batches from the background, so they must be flushed
+/// with [`Telemetry::shutdown`] before the process exits.
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Exports to the collector at the endpoint, such as
+    /// `http://localhost:4318`, which must be called from a Tokio runtime
+    pub fn new(endpoint: &str) -> Result<Self, TraceError> {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint)
+            .build_span_exporter()?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_config(
+                trace::config()
+                    .with_resource(Resource::new([KeyValue::new("service.name", "namethat")])),
+            )
+            .build();
+
+        Ok(Self { provider })
+    }
+
+    /// The layer turning the spans of the app into OpenTelemetry spans
+    ///
+    /// Only the spans of the app are exported, whatever the log filter, so
+    /// that turning logging down does not break the traces.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_opentelemetry::layer()
+            .with_tracer(self.provider.tracer("namethat"))
+            .with_filter(Targets::new().with_target("namethat", Level::INFO))
+    }
+
+    /// Sends the spans that have not been exported yet
+    pub async fn shutdown(self) {
+        let provider = self.provider;
+        let flushed = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+        match flushed {
+            Ok(results) => results
+                .into_iter()
+                .filter_map(Result::err)
+                .for_each(|e| tracing::warn!("Could not export spans: {}", e)),
+            Err(e) => tracing::warn!("Could not export spans: {}", e),
+        }
+    }
+}
+
+/// Continues the trace of the caller, when the request carries a W3C
+/// `traceparent` header
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(context);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{body::Bytes, extract::State, routing::post, Router};
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use prost::Message;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    type Received = Arc<Mutex<Vec<ExportTraceServiceRequest>>>;
+
+    /// Stands in for a collector, keeping the spans it is sent
+    fn collector() -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/v1/traces",
+                post(|State(received): State<Received>, body: Bytes| async move {
+                    let request = ExportTraceServiceRequest::decode(body).unwrap();
+                    received.lock().unwrap().push(request);
+                }),
+            )
+            .with_state(received.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        (format!("http://{}", addr), received)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_continue_the_remote_trace() {
+        let (endpoint, received) = collector();
+        let telemetry = Telemetry::new(&endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .unwrap(),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            set_remote_parent(&request, &headers);
+            request.in_scope(|| {
+                tracing::info_span!("reveal_answer").in_scope(|| ());
+            });
+        });
+        telemetry.shutdown().await;
+
+        let received = received.lock().unwrap();
+        let spans: Vec<_> = received
+            .iter()
+            .flat_map(|r| &r.resource_spans)
+            .flat_map(|r| &r.scope_spans)
+            .flat_map(|s| &s.spans)
+            .collect();
+        assert_eq!(spans.len(), 2);
+        let trace_id = hex(&spans[0].trace_id);
+        assert_eq!(trace_id, "0af7651916cd43dd8448eb211c80319c");
+        assert!(spans.iter().all(|s| hex(&s.trace_id) == trace_id));
+        let request = spans.iter().find(|s| s.name == "request").unwrap();
+        let reveal = spans.iter().find(|s| s.name == "reveal_answer").unwrap();
+        assert_eq!(hex(&request.parent_span_id), "b7ad6b7169203331");
+        assert_eq!(reveal.parent_span_id, request.span_id);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
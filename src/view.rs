@@ -14,11 +14,26 @@ impl Index {
 
 #[derive(Template)]
 #[template(path = "pages/auth/login.jinja")]
-pub struct Login {}
+pub struct Login {
+    /// The single sign-on provider to offer, if any
+    provider: Option<String>,
+    /// Why logging in with the provider failed, if it did
+    error: Option<String>,
+}
 
 impl Login {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(provider: Option<String>) -> Self {
+        Self {
+            provider,
+            error: None,
+        }
+    }
+
+    pub fn with_error(self, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..self
+        }
     }
 }
 
@@ -32,6 +47,41 @@ impl Register {
     }
 }
 
+#[derive(Template)]
+#[template(path = "pages/auth/verify-email.jinja")]
+pub struct VerifyEmail {
+    /// Why the email could not be verified, if it could not
+    error: Option<String>,
+}
+
+impl VerifyEmail {
+    pub fn new(error: Option<String>) -> Self {
+        Self { error }
+    }
+}
+
+#[derive(Default, Template)]
+#[template(path = "pages/auth/forgot-password.jinja")]
+pub struct ForgotPassword {}
+
+impl ForgotPassword {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[derive(Template)]
+#[template(path = "pages/auth/reset-password.jinja")]
+pub struct ResetPassword {
+    token: String,
+}
+
+impl ResetPassword {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "pages/profile.jinja")]
 pub struct Profile {
@@ -181,6 +231,9 @@ impl_to_html!(
     Index,
     Login,
     Register,
+    VerifyEmail,
+    ForgotPassword,
+    ResetPassword,
     Profile,
     Unauthorized,
     Forbidden,